@@ -4,14 +4,12 @@ use generic_array::GenericArray;
 use pbkdf2::pbkdf2_hmac;
 use rand::{rngs::OsRng, TryRngCore};
 use serde::{Deserialize, Serialize};
-use serde_json;
-use sha2::{Digest, Sha256};
+use sha2::Sha256;
 use std::io::{self, BufReader, Read, Write};
 use std::net::{TcpListener, TcpStream, Shutdown};
 use std::thread;
 use std::time::Duration;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use std::os::unix::io::{AsRawFd};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
 
 const RESET: &str = "\x1b[0m";
 const BOLD: &str = "\x1b[1m";
@@ -22,6 +20,37 @@ const RED: &str = "\x1b[91m";
 const YELLOW: &str = "\x1b[93m";
 const MAGENTA: &str = "\x1b[95m";
 
+/// Names of the chat sessions currently open, for the prompt. A session run
+/// as a background job keeps its entry until it ends.
+static SESSIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Removes its session from `SESSIONS` when the chat returns, however it ends.
+struct Session(String);
+
+impl Session {
+    fn open(name: &str) -> Self {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.push(name.to_string());
+        }
+        Session(name.to_string())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            if let Some(index) = sessions.iter().rposition(|name| *name == self.0) {
+                sessions.remove(index);
+            }
+        }
+    }
+}
+
+/// The most recently opened chat session still running, if any.
+pub fn active_session() -> Option<String> {
+    SESSIONS.lock().ok()?.last().cloned()
+}
+
 type GcmNonce = GenericArray<u8, <Aes256Gcm as AeadCore>::NonceSize>;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,7 +83,7 @@ pub fn start_chat(config: ChatConfig) -> io::Result<()> {
 
     let salt = b"some_fixed_salt_for_heimdal_chat";
     let mut key_bytes = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(&password.as_bytes(), salt, 100_000, &mut key_bytes);
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, 100_000, &mut key_bytes);
 
     let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
     let cipher = Aes256Gcm::new(key);
@@ -65,7 +94,7 @@ pub fn start_chat(config: ChatConfig) -> io::Result<()> {
     println!("{}>> Key derivation complete. Initializing secure channel...{}", YELLOW, RESET);
     println!("{}>> Chatting securely.{}", GREEN, RESET);
 
-
+    let _session = Session::open(&config.chat_name);
     match config.role {
         ChatRole::Host => {
             println!(
@@ -97,14 +126,14 @@ fn send_encrypted_message(
     message: &str,
 ) -> io::Result<()> {
     let mut nonce_bytes = [0u8; 12];
-    let mut rng = OsRng::default();
+    let mut rng = OsRng;
     rng.try_fill_bytes(&mut nonce_bytes).unwrap();
 
     let nonce = GcmNonce::from_slice(&nonce_bytes);
 
     let ciphertext = cipher
         .encrypt(nonce, message.as_bytes())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}ERROR: Encryption failed: {}{}", RED, e, RESET)))?;
+        .map_err(|e| io::Error::other(format!("{}ERROR: Encryption failed: {}{}", RED, e, RESET)))?;
 
     let encrypted_msg = EncryptedMessage {
         nonce: nonce_bytes.to_vec(),
@@ -112,7 +141,7 @@ fn send_encrypted_message(
     };
 
     let encoded = serde_json::to_vec(&encrypted_msg)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{}ERROR: Serialization failed: {}{}", RED, e, RESET)))?;
+        .map_err(|e| io::Error::other(format!("{}ERROR: Serialization failed: {}{}", RED, e, RESET)))?;
 
     let len_bytes = (encoded.len() as u32).to_be_bytes();
     stream.write_all(&len_bytes)?;
@@ -226,7 +255,7 @@ fn host_chat(port: u16, cipher: Aes256Gcm) -> io::Result<()> {
         }
     });
 
-    let mut user_exited_chat = false;
+    let user_exited_chat;
     println!("{}>> Session Active. Type your secure messages (press Enter to send, /exit to terminate):{}", BOLD, RESET);
     loop {
         let mut input = String::new();
@@ -302,7 +331,7 @@ fn guest_chat(ip: &str, port: u16, cipher: Aes256Gcm) -> io::Result<()> {
         }
     });
 
-    let mut user_exited_chat = false;
+    let user_exited_chat;
     println!("{}>> Session Active. Type your secure messages (press Enter to send, /exit to terminate):{}", BOLD, RESET);
     loop {
         let mut input = String::new();
@@ -1,39 +1,10 @@
+use crate::parser::{is_valid_name, split_assignment};
+use crate::shell::{Flow, Shell};
+use crate::signals;
 use rand::prelude::IndexedRandom;
 use std::env::consts;
-use std::path::Path;
-use tokio::fs;
-use walkdir::WalkDir;
-
-pub async fn search_files(keyword: &str) {
-    for entry in WalkDir::new(".").into_iter().filter_map(Result::ok) {
-        if entry.path().is_file() {
-            if let Ok(content) = fs::read_to_string(entry.path()).await {
-                if content.contains(keyword) {
-                    println!(
-                        "{}: {}",
-                        entry.path().display(),
-                        content
-                            .lines()
-                            .find(|line| line.contains(keyword))
-                            .unwrap_or("")
-                    );
-                }
-            }
-        }
-    }
-}
-
-pub fn print_tree(path: &Path, indent: usize) {
-    if let Ok(entries) = path.read_dir() {
-        for entry in entries.filter_map(Result::ok) {
-            let file_name = entry.file_name().into_string().unwrap_or_default();
-            println!("{}{}", " ".repeat(indent), file_name);
-            if entry.path().is_dir() {
-                print_tree(&entry.path(), indent + 2);
-            }
-        }
-    }
-}
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 const TIPS: &[&str] = &[
     "Did you know? Heimdal is written in Rust!",
@@ -41,40 +12,584 @@ const TIPS: &[&str] = &[
     "You can clear the screen with `clear`.",
 ];
 
-pub fn print_fortune() {
+pub fn print_fortune(out: &mut dyn Write) -> io::Result<()> {
     let mut rng = rand::rng();
     if let Some(tip) = TIPS.choose(&mut rng) {
-        println!("\x1b[1;36m💡 {tip}\x1b[0m\n");
+        writeln!(out, "\x1b[1;36m💡 {tip}\x1b[0m\n")?;
     }
+    Ok(())
 }
 
-pub fn print_help() {
-    println!("\n\x1b[1;36mHeimdal Shell\x1b[0m\n");
-    println!("Available internal commands:");
-    println!("  \x1b[1;33mcd <dir>\x1b[0m         Change directory");
-    println!("  \x1b[1;33mclear\x1b[0m           Clear the screen");
-    println!("  \x1b[1;33mexit\x1b[0m            Exit the shell");
-    println!("  \x1b[1;33mwhoami\x1b[0m          Print current user");
-    println!("  \x1b[1;33mheimdal --help\x1b[0m   Show this help message\n");
-    println!("  \x1b[1;33mtree\x1b[0m            Print folder tree");
-    println!("  \x1b[1;33mtip\x1b[0m             Show a random Heimdal tip");
-    println!("  \x1b[1;33msearch <word>\x1b[0m   Search files for a keyword");
-    println!("  \x1b[1;33mchat host <name> <port> <password>\x1b[0m   Start a chat session as host");
-    println!("  \x1b[1;33mchat guest <name> <ip> <port> <password>\x1b[0m  Join a chat session as guest");
+pub fn print_help(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "\n\x1b[1;36mHeimdal Shell\x1b[0m\n")?;
+    writeln!(out, "Available internal commands:")?;
+    writeln!(out, "  \x1b[1;33mcd [dir|-]\x1b[0m       Change directory (home by default, - for the previous one)")?;
+    writeln!(out, "  \x1b[1;33mclear\x1b[0m           Clear the screen")?;
+    writeln!(out, "  \x1b[1;33mexit [code]\x1b[0m     Exit the shell")?;
+    writeln!(out, "  \x1b[1;33msource <file>\x1b[0m   Run a script in the current shell")?;
+    writeln!(out, "  \x1b[1;33mwhoami\x1b[0m          Print current user")?;
+    writeln!(out, "  \x1b[1;33mexport NAME=value\x1b[0m  Set a variable and pass it to commands")?;
+    writeln!(out, "  \x1b[1;33munset [-f] NAME\x1b[0m  Remove a variable, or a function with -f")?;
+    writeln!(out, "  \x1b[1;33menv\x1b[0m             List exported variables")?;
+    writeln!(out, "  \x1b[1;33malias name=value\x1b[0m  Define a command alias; unalias removes it")?;
+    writeln!(out, "  \x1b[1;33mabbr name text\x1b[0m   Define an abbreviation expanded as you type")?;
+    writeln!(out, "  \x1b[1;33mhistory [n|-c]\x1b[0m  Show or clear history; !! and !n rerun commands")?;
+    writeln!(out, "  \x1b[1;33mjobs, fg, bg\x1b[0m    List jobs, or resume one (%n) in the foreground or background")?;
+    writeln!(out, "  \x1b[1;33mwait, disown\x1b[0m    Wait for background jobs, or let one run on unattended")?;
+    writeln!(out, "  \x1b[1;33mheimdal --help\x1b[0m   Show this help message\n")?;
+    writeln!(out, "  \x1b[1;33mtree [-asr] [-L depth] [--sort name|size|mtime] [path]\x1b[0m  Print folder tree, skipping ignored and hidden files")?;
+    writeln!(out, "  \x1b[1;33mtip\x1b[0m             Show a random Heimdal tip")?;
+    writeln!(out, "  \x1b[1;33msearch [-Eiwlc] [-C N] [-g glob] [-t type] [-d depth] <pattern> [path]\x1b[0m  Search files for text, or a regex with -E; skips ignored, hidden and binary files")?;
+    writeln!(out, "  \x1b[1;33msearch -r <text> <pattern> [path]\x1b[0m  Replace matches after a diff preview and confirmation; search --undo reverts")?;
+    writeln!(out, "  \x1b[1;33mchat host <name> <port> <password>\x1b[0m   Start a chat session as host")?;
+    writeln!(out, "  \x1b[1;33mchat guest <name> <ip> <port> <password>\x1b[0m  Join a chat session as guest")?;
 
-    println!(
+    writeln!(
+        out,
         "External commands like \x1b[1;32mecho\x1b[0m or \x1b[1;32mls\x1b[0m are passed to the OS."
-    );
-    println!("You can run any system command available in your environment.\n");
+    )?;
+    writeln!(out, "You can run any system command available in your environment.")?;
+    writeln!(
+        out,
+        "Chain commands with \x1b[1;32m|\x1b[0m, e.g. \x1b[1;32mtree | grep src\x1b[0m."
+    )?;
+    writeln!(
+        out,
+        "End a command with \x1b[1;32m&\x1b[0m to run it in the background; \x1b[1;32mCtrl-Z\x1b[0m suspends the running one."
+    )?;
+    writeln!(
+        out,
+        "Sequence them with \x1b[1;32m;\x1b[0m, \x1b[1;32m&&\x1b[0m and \x1b[1;32m||\x1b[0m; \x1b[1;32m$?\x1b[0m holds the last exit status."
+    )?;
+    writeln!(
+        out,
+        "Redirect with \x1b[1;32m>\x1b[0m, \x1b[1;32m>>\x1b[0m, \x1b[1;32m<\x1b[0m, \x1b[1;32m2>\x1b[0m and \x1b[1;32m2>&1\x1b[0m, e.g. \x1b[1;32mtree > tree.txt\x1b[0m."
+    )?;
+    writeln!(
+        out,
+        "Use a command's output as arguments with \x1b[1;32m$(cmd)\x1b[0m, and as a file with \x1b[1;32m<(cmd)\x1b[0m, e.g. \x1b[1;32mdiff <(ls a) <(ls b)\x1b[0m."
+    )?;
+    writeln!(
+        out,
+        "Scripts can use \x1b[1;32mif\x1b[0m, \x1b[1;32mwhile\x1b[0m, \x1b[1;32muntil\x1b[0m, \x1b[1;32mfor\x1b[0m and \x1b[1;32mcase\x1b[0m, with \x1b[1;32mbreak\x1b[0m and \x1b[1;32mcontinue\x1b[0m inside loops."
+    )?;
+    writeln!(
+        out,
+        "Define functions with \x1b[1;32mfn name {{ ... }}\x1b[0m; they see their arguments as \x1b[1;32m$1\x1b[0m, \x1b[1;32m$@\x1b[0m and \x1b[1;32m$#\x1b[0m and can use \x1b[1;32mlocal\x1b[0m and \x1b[1;32mreturn\x1b[0m."
+    )?;
+    writeln!(
+        out,
+        "Run scripts with \x1b[1;32mheimdal script.hd [args]\x1b[0m or \x1b[1;32mheimdal -c \"command\"\x1b[0m; \x1b[1;32m$1\x1b[0m, \x1b[1;32m$2\x1b[0m... hold the arguments."
+    )?;
+    writeln!(
+        out,
+        "A line left inside quotes or an open block, or ending in \x1b[1;32m|\x1b[0m, \x1b[1;32m&&\x1b[0m or \x1b[1;32m\\\x1b[0m, continues on the next line, and the whole command stays editable until it is complete."
+    )?;
+    writeln!(
+        out,
+        "Unknown commands show in \x1b[1;31mred\x1b[0m as you type; press \x1b[1;32m→\x1b[0m to accept the grey suggestion from history."
+    )?;
+    writeln!(
+        out,
+        "Set the prompt with \x1b[1;32mPS1\x1b[0m: \x1b[1;32m\\u\x1b[0m user, \x1b[1;32m\\w\x1b[0m directory, \x1b[1;32m\\g\x1b[0m git branch, \x1b[1;32m\\?\x1b[0m failed status, \x1b[1;32m\\D\x1b[0m slow command time, \x1b[1;32m\\c\x1b[0m chat session;"
+    )?;
+    writeln!(
+        out,
+        "text inside \x1b[1;32m\\( ... \\)\x1b[0m only shows when one of those has a value, e.g. \x1b[1;32mPS1='\\w\\( (\\g)\\)\\$ '\x1b[0m."
+    )?;
+    writeln!(
+        out,
+        "Interactive sessions first run \x1b[1;32m/etc/heimdalrc\x1b[0m and \x1b[1;32m~/.heimdalrc\x1b[0m.\n"
+    )?;
 
     let os = consts::OS;
     if os == "windows" {
-        println!(
+        writeln!(
+            out,
             "⚠️  Some Unix commands like `cat`, `grep`, or `touch` may not work unless you install Git Bash or enable WSL."
-        );
+        )?;
+    }
+    Ok(())
+}
+
+/// Changes directory and updates `PWD` and `OLDPWD`. With no argument it goes
+/// to `$HOME`, `-` returns to `$OLDPWD`, and relative names are also looked up
+/// in the `:`-separated `$CDPATH`.
+pub fn change_dir(
+    target: Option<&str>,
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let (dir, print) = match target {
+        None => match shell.var("HOME") {
+            Some(home) => (PathBuf::from(home), false),
+            None => {
+                writeln!(err, "cd: HOME not set")?;
+                return Ok(1);
+            }
+        },
+        Some("-") => match shell.var("OLDPWD") {
+            Some(old) => (PathBuf::from(old), true),
+            None => {
+                writeln!(err, "cd: OLDPWD not set")?;
+                return Ok(1);
+            }
+        },
+        Some(dir) => search_cdpath(dir, shell),
+    };
+
+    let old = shell.current_dir().ok();
+    if let Err(e) = shell.set_current_dir(&dir) {
+        writeln!(err, "cd: {}: {e}", dir.display())?;
+        return Ok(1);
+    }
+
+    if let Some(old) = old {
+        shell.export_var("OLDPWD", Some(old.display().to_string()));
+    }
+    if let Ok(new) = shell.current_dir() {
+        shell.export_var("PWD", Some(new.display().to_string()));
+        if print {
+            writeln!(out, "{}", new.display())?;
+        }
+    }
+    Ok(0)
+}
+
+// Returns the directory to enter and whether it came from a non-empty
+// `CDPATH` entry, in which case `cd` prints where it went.
+fn search_cdpath(dir: &str, shell: &Shell) -> (PathBuf, bool) {
+    let explicit = dir.starts_with('/')
+        || dir == "."
+        || dir == ".."
+        || dir.starts_with("./")
+        || dir.starts_with("../");
+    if !explicit {
+        for entry in shell.var("CDPATH").unwrap_or_default().split(':') {
+            let base = if entry.is_empty() { "." } else { entry };
+            let candidate = Path::new(base).join(dir);
+            if shell.resolve(&candidate).is_dir() {
+                return (candidate, !entry.is_empty());
+            }
+        }
+    }
+    (PathBuf::from(dir), false)
+}
+
+/// `export NAME=value` sets and exports, `export NAME` exports an existing
+/// variable and a bare `export` lists every exported variable.
+pub fn export_vars(
+    args: &[String],
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    if args.is_empty() {
+        let mut exported: Vec<_> = shell.exported_vars().collect();
+        exported.sort();
+        for (name, value) in exported {
+            writeln!(out, "export {name}={}", single_quote(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match split_assignment(arg) {
+            Some((name, value)) => shell.export_var(name, Some(value.to_string())),
+            None if is_valid_name(arg) => shell.export_var(arg, None),
+            None => {
+                writeln!(err, "export: `{arg}': not a valid identifier")?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `unset NAME...` removes variables and `unset -f NAME...` functions.
+pub fn unset_vars(names: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if names.first().is_some_and(|flag| flag == "-f") {
+        for name in &names[1..] {
+            shell.functions.remove(name);
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for name in names {
+        if is_valid_name(name) {
+            shell.unset_var(name);
+        } else {
+            writeln!(err, "unset: `{name}': not a valid identifier")?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `alias name=value` defines an alias, `alias name` shows one and a bare
+/// `alias` lists them all.
+pub fn define_aliases(
+    args: &[String],
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    if args.is_empty() {
+        for (name, value) in &shell.aliases {
+            writeln!(out, "alias {name}={}", single_quote(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if is_valid_alias_name(name) => {
+                shell.aliases.insert(name.to_string(), value.to_string());
+            }
+            Some((name, _)) => {
+                writeln!(err, "alias: `{name}': invalid alias name")?;
+                status = 1;
+            }
+            None => match shell.aliases.get(arg) {
+                Some(value) => writeln!(out, "alias {arg}={}", single_quote(value))?,
+                None => {
+                    writeln!(err, "alias: {arg}: not found")?;
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+/// `unalias name...` removes aliases and `unalias -a` removes all of them.
+pub fn remove_aliases(names: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if names.is_empty() {
+        writeln!(err, "unalias: usage: unalias [-a] name ...")?;
+        return Ok(2);
+    }
+    if names.iter().any(|name| name == "-a") {
+        shell.aliases.clear();
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for name in names {
+        if shell.aliases.remove(name).is_none() {
+            writeln!(err, "unalias: {name}: not found")?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `abbr name expansion...` defines an abbreviation that the line editor
+/// replaces as soon as a space is typed after it, `abbr -e name` erases one
+/// and a bare `abbr` lists them all.
+pub fn abbreviations(
+    args: &[String],
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    match args {
+        [] => {
+            for (name, expansion) in &shell.abbreviations {
+                writeln!(out, "abbr {name} {}", single_quote(expansion))?;
+            }
+            Ok(0)
+        }
+        [flag, names @ ..] if flag == "-e" || flag == "--erase" => {
+            let mut status = 0;
+            for name in names {
+                if shell.abbreviations.remove(name).is_none() {
+                    writeln!(err, "abbr: {name}: not found")?;
+                    status = 1;
+                }
+            }
+            Ok(status)
+        }
+        [name] => match shell.abbreviations.get(name) {
+            Some(expansion) => {
+                writeln!(out, "abbr {name} {}", single_quote(expansion))?;
+                Ok(0)
+            }
+            None => {
+                writeln!(err, "abbr: {name}: not found")?;
+                Ok(1)
+            }
+        },
+        [name, expansion @ ..] => {
+            if !is_valid_alias_name(name) {
+                writeln!(err, "abbr: `{name}': invalid abbreviation name")?;
+                return Ok(1);
+            }
+            shell
+                .abbreviations
+                .insert(name.to_string(), expansion.join(" "));
+            Ok(0)
+        }
+    }
+}
+
+fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "=/$`'\"\\|&;<>()".contains(c))
+}
+
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub fn print_env(shell: &Shell, out: &mut dyn Write) -> io::Result<()> {
+    let mut exported: Vec<_> = shell.exported_vars().collect();
+    exported.sort();
+    for (name, value) in exported {
+        writeln!(out, "{name}={value}")?;
+    }
+    Ok(())
+}
+
+/// `break [n]` and `continue [n]`: leaves or restarts the `n`th enclosing loop,
+/// the innermost by default.
+pub fn loop_control(
+    name: &str,
+    count: Option<&str>,
+    flow: fn(usize) -> Flow,
+    shell: &mut Shell,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let count = match count.map(str::parse::<usize>) {
+        None => 1,
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => {
+            writeln!(err, "{name}: {}: loop count out of range", count.unwrap_or_default())?;
+            return Ok(1);
+        }
+    };
+    if shell.loop_depth == 0 {
+        writeln!(err, "{name}: only meaningful in a `for', `while', or `until' loop")?;
+        return Ok(0);
+    }
+
+    shell.flow = flow(count.min(shell.loop_depth));
+    Ok(0)
+}
+
+/// `local NAME[=value]...` gives the running function its own copy of each
+/// variable, dropped again when the function returns.
+pub fn local_vars(args: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match split_assignment(arg) {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None if is_valid_name(arg) => (arg.as_str(), None),
+            None => {
+                writeln!(err, "local: `{arg}': not a valid identifier")?;
+                status = 1;
+                continue;
+            }
+        };
+        if !shell.declare_local(name, value) {
+            writeln!(err, "local: can only be used in a function")?;
+            return Ok(1);
+        }
+    }
+    Ok(status)
+}
+
+/// `return [n]` leaves the running function or sourced script with status `n`,
+/// or with the status of the last command.
+pub fn return_from(code: Option<&str>, shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if shell.call_depth == 0 {
+        writeln!(err, "return: can only `return' from a function or sourced script")?;
+        return Ok(1);
+    }
+    let status = match code.map(str::parse::<i32>) {
+        None => shell.last_status,
+        Some(Ok(code)) => code & 0xff,
+        Some(Err(_)) => {
+            writeln!(err, "return: {}: numeric argument required", code.unwrap_or_default())?;
+            2
+        }
+    };
+    shell.flow = Flow::Return;
+    Ok(status)
+}
+
+/// `jobs [-l|-p]` lists background and stopped jobs, with their process ids
+/// for `-l`, or only the process ids for `-p`.
+pub async fn list_jobs(
+    args: &[String],
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    match args.first().map(String::as_str) {
+        None => shell.jobs.list(false, out).await.map(|_| 0),
+        Some("-l") => shell.jobs.list(true, out).await.map(|_| 0),
+        Some("-p") => shell.jobs.list_pids(out).await.map(|_| 0),
+        Some(arg) => writeln!(err, "jobs: {arg}: invalid option").map(|_| 2),
+    }
+}
+
+/// `fg [job]` resumes a job, the current one by default, in the foreground
+/// and waits for it.
+pub async fn foreground_job(
+    spec: Option<&str>,
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    if !shell.jobs.job_control() {
+        writeln!(err, "fg: no job control")?;
+        return Ok(1);
+    }
+    match shell.jobs.find(spec) {
+        Ok(index) => {
+            writeln!(out, "{}", shell.jobs.command(index))?;
+            out.flush()?;
+            Ok(shell.jobs.foreground(index).await)
+        }
+        Err(e) => writeln!(err, "fg: {e}").map(|_| 1),
     }
 }
 
+/// `bg [job...]` lets stopped jobs, the current one by default, carry on in
+/// the background.
+pub fn background_jobs(specs: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if !shell.jobs.job_control() {
+        writeln!(err, "bg: no job control")?;
+        return Ok(1);
+    }
+    let specs: Vec<Option<&str>> = if specs.is_empty() {
+        vec![None]
+    } else {
+        specs.iter().map(|spec| Some(spec.as_str())).collect()
+    };
+    let mut status = 0;
+    for spec in specs {
+        match shell.jobs.find(spec) {
+            Ok(index) => shell.jobs.background(index, err)?,
+            Err(e) => {
+                writeln!(err, "bg: {e}")?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `wait [job|pid...]` waits for the given jobs, or for all of them, and
+/// returns the status of the last one given.
+pub async fn wait_jobs(args: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if args.is_empty() {
+        for id in shell.jobs.ids() {
+            if let Ok(index) = shell.jobs.find(Some(&format!("%{id}"))) {
+                if shell.jobs.wait_for(index).await == 130 && signals::interrupted() {
+                    return Ok(130);
+                }
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let index = match arg.parse::<i32>() {
+            Ok(pid) => shell
+                .jobs
+                .find_pid(pid)
+                .ok_or_else(|| format!("pid {pid} is not a child of this shell")),
+            Err(_) => shell.jobs.find(Some(arg)),
+        };
+        status = match index {
+            Ok(index) => shell.jobs.wait_for(index).await,
+            Err(e) => {
+                writeln!(err, "wait: {e}")?;
+                127
+            }
+        };
+    }
+    Ok(status)
+}
+
+/// `disown [-a] [job...]` removes jobs from the table, the current one by
+/// default or all of them with `-a`, leaving them running.
+pub fn disown_jobs(args: &[String], shell: &mut Shell, err: &mut dyn Write) -> io::Result<i32> {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        for _ in shell.jobs.ids() {
+            shell.jobs.disown(0);
+        }
+        return Ok(0);
+    }
+
+    let specs: Vec<Option<&str>> = if args.is_empty() {
+        vec![None]
+    } else {
+        args.iter().map(|arg| Some(arg.as_str())).collect()
+    };
+    let mut status = 0;
+    for spec in specs {
+        match shell.jobs.find(spec) {
+            Ok(index) => shell.jobs.disown(index),
+            Err(e) => {
+                writeln!(err, "disown: {e}")?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Lists the command history, or only the last `N` entries with `history N`.
+/// `history -c` clears it.
+pub fn history(
+    args: &[String],
+    shell: &mut Shell,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let entries = shell.history.entries();
+    let count = match args.first().map(String::as_str) {
+        None => entries.len(),
+        // A copy of the shell shares the history file, so clearing it from
+        // a pipeline or subshell would reach further than the copy.
+        Some("-c") if shell.is_subshell() => {
+            writeln!(err, "history: -c only works in the shell itself")?;
+            return Ok(1);
+        }
+        Some("-c") => {
+            shell.history.clear()?;
+            return Ok(0);
+        }
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count.min(entries.len()),
+            Err(_) => {
+                writeln!(err, "history: {arg}: numeric argument required")?;
+                return Ok(1);
+            }
+        },
+    };
+
+    let start = entries.len() - count;
+    for (index, entry) in entries.iter().enumerate().skip(start) {
+        writeln!(out, "{:5}  {entry}", index + 1)?;
+    }
+    Ok(0)
+}
+
+/// `1 file`, `3 files`.
+pub fn plural(count: usize, one: &str, many: &str) -> String {
+    format!("{count} {}", if count == 1 { one } else { many })
+}
+
 pub fn get_os() -> &'static str {
     consts::OS
 }
@@ -1,15 +1,38 @@
 use crate::chat::ChatConfig;
 
 pub enum ShellCommand {
-    Exit,
+    Exit(Option<String>),
     Clear,
     Os,
-    Tree,
+    Tree(Vec<String>),
     Tip,
-    Search(String),
-    Cd(String),
+    Search(Vec<String>),
+    Cd(Option<String>),
     Whoami,
+    Export(Vec<String>),
+    Unset(Vec<String>),
+    Env,
+    History(Vec<String>),
+    Source(Vec<String>),
+    Alias(Vec<String>),
+    Unalias(Vec<String>),
+    Abbr(Vec<String>),
+    Break(Option<String>),
+    Continue(Option<String>),
+    Local(Vec<String>),
+    Return(Option<String>),
+    Jobs(Vec<String>),
+    Fg(Option<String>),
+    Bg(Vec<String>),
+    Wait(Vec<String>),
+    Disown(Vec<String>),
     HeimdalHelp,
     Chat(ChatConfig),
     Unknown(String, Vec<String>),
-}
\ No newline at end of file
+}
+/// Names recognised by `parse_command`, offered by tab completion.
+pub const BUILTIN_NAMES: &[&str] = &[
+    "abbr", "alias", "bg", "break", "cd", "chat", "clear", "continue", "disown", "env", "exit",
+    "export", "fg", "fortune", "heimdal", "history", "jobs", "local", "os", "return", "search",
+    "source", "tip", "tree", "unalias", "unset", "wait", "whoami",
+];
@@ -1 +1,2 @@
+#[allow(clippy::module_inception)]
 pub(crate) mod commands_enum;
\ No newline at end of file
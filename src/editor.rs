@@ -0,0 +1,653 @@
+use crate::commands_enum::commands_enum::BUILTIN_NAMES;
+use crate::parser::{continuation, is_reserved, Continuation, split_assignment, starts_command};
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::SearchDirection;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const RESET: &str = "\x1b[0m";
+const EXECUTABLE: &str = "\x1b[1;32m";
+const BUILTIN: &str = "\x1b[1;36m";
+const UNKNOWN: &str = "\x1b[1;31m";
+const KEYWORD: &str = "\x1b[1;34m";
+const STRING: &str = "\x1b[33m";
+const VARIABLE: &str = "\x1b[35m";
+const OPERATOR: &str = "\x1b[1m";
+const FADED: &str = "\x1b[90m";
+
+/// Abbreviations shared between the line editor helper and the space key
+/// handler.
+pub type Abbreviations = Arc<Mutex<BTreeMap<String, String>>>;
+
+/// Line editor helper. The shell refreshes `path`, `home`, `aliases`,
+/// `functions` and `abbreviations` before each prompt so completion sees the same state as
+/// the commands it runs.
+#[derive(Default)]
+pub struct EditorHelper {
+    pub path: String,
+    pub home: Option<String>,
+    pub aliases: BTreeMap<String, String>,
+    pub functions: Vec<String>,
+    pub abbreviations: Abbreviations,
+    // Executables found on `path`, rescanned whenever `path` changes.
+    executables: RefCell<Option<(String, BTreeSet<String>)>>,
+}
+
+impl Helper for EditorHelper {}
+
+impl Hinter for EditorHelper {
+    type Hint = String;
+
+    /// Offers the rest of the latest history entry that starts with the line
+    /// typed so far, as fish does. The right arrow accepts it.
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if line.trim().is_empty() || pos < line.len() {
+            return None;
+        }
+        let history = ctx.history();
+        (0..history.len())
+            .rev()
+            .filter_map(|index| history.get(index, SearchDirection::Forward).ok().flatten())
+            .find(|found| {
+                found.entry.len() > line.len()
+                    && found.entry.starts_with(line)
+                    && !found.entry.contains('\n')
+            })
+            .map(|found| found.entry[line.len()..].to_string())
+    }
+}
+
+impl Highlighter for EditorHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let mut out = String::with_capacity(line.len() * 2);
+        self.colour_commands(line, &mut out);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{FADED}{hint}{RESET}"))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl Validator for EditorHelper {
+    /// Keeps Enter from running a command that is not finished, such as one
+    /// left inside quotes, after `|` or `&&`, after a trailing backslash or
+    /// in an open `if` or `{` block. The next line is added to the same
+    /// buffer, so the whole command can still be edited before it runs.
+    fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
+        Ok(match continuation(ctx.input(), &self.aliases) {
+            Some(Continuation::Join | Continuation::Newline) => ValidationResult::Incomplete,
+            None => ValidationResult::Valid(None),
+        })
+    }
+}
+
+impl Completer for EditorHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if let Some((start, expansion)) = abbreviation_at(&self.abbreviations, line, pos) {
+            return Ok((
+                start,
+                vec![Pair {
+                    replacement: format!("{expansion} "),
+                    display: expansion,
+                }],
+            ));
+        }
+
+        let before = &line[..pos];
+        let (start, mut words) = split_for_completion(before);
+        let word = &before[start..];
+        let assignments = words
+            .iter()
+            .take_while(|word| split_assignment(word).is_some())
+            .count();
+        words.drain(..assignments);
+
+        let candidates = match words.first().map(String::as_str) {
+            None if !word.contains('/') => self.complete_command(word),
+            None => self.complete_path(word, PathKind::Executables),
+            Some("cd") => self.complete_path(word, PathKind::Directories),
+            Some("chat") if words.len() == 1 => ["host", "guest"]
+                .iter()
+                .filter(|role| role.starts_with(word))
+                .map(|role| Pair {
+                    display: role.to_string(),
+                    replacement: format!("{role} "),
+                })
+                .collect(),
+            Some(_) => self.complete_path(word, PathKind::Any),
+        };
+
+        Ok((start, candidates))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PathKind {
+    Any,
+    Directories,
+    Executables,
+}
+
+impl EditorHelper {
+    fn complete_command(&self, prefix: &str) -> Vec<Pair> {
+        let mut names: BTreeSet<String> = BUILTIN_NAMES
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| name.to_string())
+            .collect();
+
+        names.extend(
+            self.aliases
+                .keys()
+                .chain(&self.functions)
+                .filter(|name| name.starts_with(prefix))
+                .cloned(),
+        );
+
+        self.with_executables(|executables| {
+            names.extend(
+                executables
+                    .iter()
+                    .filter(|name| name.starts_with(prefix))
+                    .cloned(),
+            );
+        });
+
+        names
+            .into_iter()
+            .map(|name| Pair {
+                replacement: format!("{} ", escape(&name)),
+                display: name,
+            })
+            .collect()
+    }
+
+    fn with_executables<R>(&self, f: impl FnOnce(&BTreeSet<String>) -> R) -> R {
+        let mut cache = self.executables.borrow_mut();
+        let (_, executables) = match cache.take() {
+            Some((path, executables)) if path == self.path => cache.insert((path, executables)),
+            _ => cache.insert((self.path.clone(), scan_path(&self.path))),
+        };
+        f(executables)
+    }
+
+    fn complete_path(&self, word: &str, kind: PathKind) -> Vec<Pair> {
+        let typed = unescape(word);
+        let (dir, prefix) = match typed.rfind('/') {
+            Some(index) => typed.split_at(index + 1),
+            None => ("", typed.as_str()),
+        };
+        let search_dir = match (dir.strip_prefix('~'), &self.home) {
+            (Some(rest), Some(home)) => format!("{home}{rest}"),
+            _ if dir.is_empty() => ".".to_string(),
+            _ => dir.to_string(),
+        };
+
+        let Ok(entries) = fs::read_dir(&search_dir) else {
+            return Vec::new();
+        };
+        let mut pairs: Vec<Pair> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.'))
+                {
+                    return None;
+                }
+                let is_dir = entry.path().is_dir();
+                let wanted = match kind {
+                    PathKind::Any => true,
+                    PathKind::Directories => is_dir,
+                    PathKind::Executables => is_dir || is_executable(&entry.path()),
+                };
+                if !wanted {
+                    return None;
+                }
+
+                let suffix = if is_dir { "/" } else { " " };
+                Some(Pair {
+                    display: format!("{name}{}", if is_dir { "/" } else { "" }),
+                    replacement: format!("{}{}{suffix}", escape(dir), escape(&name)),
+                })
+            })
+            .collect();
+        pairs.sort_by(|a, b| a.display.cmp(&b.display));
+        pairs
+    }
+}
+
+/// What a word in command position would run, for colouring.
+enum CommandKind {
+    Keyword,
+    Builtin,
+    Executable,
+    Unknown,
+}
+
+impl EditorHelper {
+    /// Colours a command line for display: command names by what they would
+    /// run, with unknown ones in red, then keywords, quoted strings,
+    /// expansions and operators. The text itself is left unchanged.
+    fn colour_commands(&self, line: &str, out: &mut String) {
+        let mut command_position = true;
+        let mut redirect_target = false;
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if c.is_whitespace() {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            if c == '#' {
+                paint(out, FADED, rest);
+                return;
+            }
+            let len = operator_len(rest);
+            if len > 0 {
+                let operator = &rest[..len];
+                paint(out, OPERATOR, operator);
+                if operator.ends_with(['<', '>', '&']) && operator.contains(['<', '>']) {
+                    redirect_target = true;
+                } else {
+                    redirect_target = false;
+                    command_position = true;
+                }
+                rest = &rest[len..];
+                continue;
+            }
+
+            let word = &rest[..word_len(rest)];
+            rest = &rest[word.len()..];
+            if redirect_target || !command_position {
+                redirect_target = false;
+                self.colour_word(word, "", out);
+                continue;
+            }
+            if split_assignment(word).is_some() {
+                self.colour_word(word, "", out);
+                continue;
+            }
+            if word.contains(['$', '`']) {
+                command_position = false;
+                self.colour_word(word, "", out);
+                continue;
+            }
+            let colour = match self.command_kind(word) {
+                CommandKind::Keyword => {
+                    command_position = starts_command(word);
+                    KEYWORD
+                }
+                CommandKind::Builtin => BUILTIN,
+                CommandKind::Executable => EXECUTABLE,
+                CommandKind::Unknown => UNKNOWN,
+            };
+            if colour != KEYWORD {
+                command_position = false;
+            }
+            self.colour_word(word, colour, out);
+        }
+    }
+
+    fn command_kind(&self, word: &str) -> CommandKind {
+        let name = unescape(word);
+        if name == word && is_reserved(word) {
+            return CommandKind::Keyword;
+        }
+        if BUILTIN_NAMES.contains(&name.as_str())
+            || self.aliases.contains_key(&name)
+            || self.functions.contains(&name)
+        {
+            return CommandKind::Builtin;
+        }
+        let found = if name.contains('/') {
+            let path = match (name.strip_prefix('~'), &self.home) {
+                (Some(rest), Some(home)) => format!("{home}{rest}"),
+                _ => name,
+            };
+            is_executable(Path::new(&path))
+        } else {
+            self.with_executables(|executables| executables.contains(&name))
+        };
+        if found {
+            CommandKind::Executable
+        } else {
+            CommandKind::Unknown
+        }
+    }
+
+    /// Paints one word in `base`, except for quoted parts and expansions.
+    fn colour_word(&self, word: &str, base: &str, out: &mut String) {
+        let mut plain = 0;
+        let mut index = 0;
+        while index < word.len() {
+            let len = match word.as_bytes()[index] {
+                b'\\' => 1 + next_char_len(&word[index + 1..]),
+                b'\'' => word[index + 1..].find('\'').map_or(word.len() - index, |end| end + 2),
+                b'"' => double_quoted_len(&word[index..]),
+                b'$' | b'`' => expansion_len(&word[index..]),
+                _ => 0,
+            };
+            if len == 0 || word.as_bytes()[index] == b'\\' {
+                index += len.max(next_char_len(&word[index..]));
+                continue;
+            }
+            paint(out, base, &word[plain..index]);
+            let piece = &word[index..index + len];
+            if piece.starts_with('"') {
+                self.colour_double_quoted(piece, out);
+            } else if piece.starts_with('\'') {
+                paint(out, STRING, piece);
+            } else {
+                self.colour_expansion(piece, out);
+            }
+            index += len;
+            plain = index;
+        }
+        paint(out, base, &word[plain..]);
+    }
+
+    fn colour_double_quoted(&self, text: &str, out: &mut String) {
+        let mut plain = 0;
+        let mut index = 1;
+        while index < text.len() {
+            let len = match text.as_bytes()[index] {
+                b'$' | b'`' => expansion_len(&text[index..]),
+                _ => 0,
+            };
+            if len == 0 {
+                let step = match text.as_bytes()[index] {
+                    b'\\' => 1 + next_char_len(&text[index + 1..]),
+                    _ => next_char_len(&text[index..]),
+                };
+                index += step;
+                continue;
+            }
+            paint(out, STRING, &text[plain..index]);
+            self.colour_expansion(&text[index..index + len], out);
+            index += len;
+            plain = index;
+        }
+        paint(out, STRING, &text[plain..]);
+    }
+
+    /// Paints `$name`, `${...}`, or a command substitution with its command
+    /// coloured like any other.
+    fn colour_expansion(&self, text: &str, out: &mut String) {
+        let (open, inner, close) = if let Some(rest) = text.strip_prefix("$(") {
+            match closing_paren(rest) {
+                Some(end) => ("$(", &rest[..end], ")"),
+                None => ("$(", rest, ""),
+            }
+        } else if let Some(rest) = text.strip_prefix('`') {
+            match rest.strip_suffix('`') {
+                Some(inner) => ("`", inner, "`"),
+                None => ("`", rest, ""),
+            }
+        } else {
+            paint(out, VARIABLE, text);
+            return;
+        };
+        paint(out, VARIABLE, open);
+        self.colour_commands(inner, out);
+        paint(out, VARIABLE, close);
+    }
+}
+
+fn paint(out: &mut String, colour: &str, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if colour.is_empty() {
+        out.push_str(text);
+    } else {
+        out.push_str(colour);
+        out.push_str(text);
+        out.push_str(RESET);
+    }
+}
+
+fn next_char_len(text: &str) -> usize {
+    text.chars().next().map_or(0, char::len_utf8)
+}
+
+/// Length of the operator `text` starts with, if any. Redirections include
+/// a leading file descriptor number, as in `2>&1`.
+fn operator_len(text: &str) -> usize {
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    if digits > 0 {
+        let rest = &text[digits..];
+        return match [">>", ">&", "<&", ">", "<"].iter().find(|op| rest.starts_with(*op)) {
+            Some(op) => digits + op.len(),
+            None => 0,
+        };
+    }
+    [
+        "&&", "||", ";;", ">>", ">&", "<&", "&>", "|", "&", ";", "(", ")", ">", "<",
+    ]
+    .iter()
+    .find(|op| text.starts_with(*op))
+    .map_or(0, |op| op.len())
+}
+
+/// Length of the word `text` starts with, keeping quotes and expansions
+/// whole even when they contain blanks or operators.
+fn word_len(text: &str) -> usize {
+    let mut index = 0;
+    while index < text.len() {
+        let len = match text.as_bytes()[index] {
+            b' ' | b'\t' | b'\n' | b'|' | b'&' | b';' | b'<' | b'>' | b'(' | b')' => break,
+            b'\\' => 1 + next_char_len(&text[index + 1..]),
+            b'\'' => text[index + 1..].find('\'').map_or(text.len() - index, |end| end + 2),
+            b'"' => double_quoted_len(&text[index..]),
+            b'$' | b'`' => expansion_len(&text[index..]).max(1),
+            _ => next_char_len(&text[index..]),
+        };
+        index += len;
+    }
+    index.min(text.len())
+}
+
+/// Length of the double-quoted string `text` starts with, through the
+/// closing quote or to the end if it is still open.
+fn double_quoted_len(text: &str) -> usize {
+    let mut index = 1;
+    while index < text.len() {
+        index += match text.as_bytes()[index] {
+            b'"' => return index + 1,
+            b'\\' => 1 + next_char_len(&text[index + 1..]),
+            b'$' | b'`' => expansion_len(&text[index..]).max(1),
+            _ => next_char_len(&text[index..]),
+        };
+    }
+    text.len()
+}
+
+/// Length of the expansion at the start of `text`, or 0 for a lone `$`.
+fn expansion_len(text: &str) -> usize {
+    if let Some(rest) = text.strip_prefix('`') {
+        return rest.find('`').map_or(text.len(), |end| end + 2);
+    }
+    let Some(rest) = text.strip_prefix('$') else {
+        return 0;
+    };
+    if let Some(inner) = rest.strip_prefix('(') {
+        return closing_paren(inner).map_or(text.len(), |end| end + 3);
+    }
+    if rest.starts_with('{') {
+        return rest.find('}').map_or(text.len(), |end| end + 2);
+    }
+    let name = rest
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        .count();
+    match rest.bytes().next() {
+        Some(b) if b.is_ascii_alphabetic() || b == b'_' => 1 + name,
+        Some(b'0'..=b'9' | b'?' | b'#' | b'@' | b'*' | b'!' | b'$' | b'-') => 2,
+        _ => 0,
+    }
+}
+
+/// Index of the `)` closing a parenthesis opened just before `text`.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = 0;
+    while index < text.len() {
+        index += match text.as_bytes()[index] {
+            b'(' => {
+                depth += 1;
+                1
+            }
+            b')' if depth == 0 => return Some(index),
+            b')' => {
+                depth -= 1;
+                1
+            }
+            b'\\' => 1 + next_char_len(&text[index + 1..]),
+            b'\'' => text[index + 1..].find('\'').map(|end| end + 2)?,
+            b'"' => double_quoted_len(&text[index..]),
+            b'`' => expansion_len(&text[index..]),
+            _ => next_char_len(&text[index..]),
+        };
+    }
+    None
+}
+
+/// Bound to the space key: when the word before the cursor is an
+/// abbreviation, asks for completion so the helper can swap in its expansion.
+pub struct AbbreviationHandler {
+    abbreviations: Abbreviations,
+}
+
+impl AbbreviationHandler {
+    pub fn new(abbreviations: Abbreviations) -> Self {
+        AbbreviationHandler { abbreviations }
+    }
+}
+
+impl ConditionalEventHandler for AbbreviationHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        abbreviation_at(&self.abbreviations, ctx.line(), ctx.pos()).map(|_| Cmd::Complete)
+    }
+}
+
+/// Returns where the abbreviation ending at the cursor starts and its
+/// expansion. Abbreviations only apply in command position.
+fn abbreviation_at(
+    abbreviations: &Abbreviations,
+    line: &str,
+    pos: usize,
+) -> Option<(usize, String)> {
+    let (before, after) = line.split_at(pos);
+    if after.starts_with(|c: char| !c.is_whitespace()) {
+        return None;
+    }
+    let (start, words) = split_for_completion(before);
+    if words.iter().any(|word| split_assignment(word).is_none()) {
+        return None;
+    }
+
+    let abbreviations = abbreviations.lock().ok()?;
+    let expansion = abbreviations.get(&before[start..])?;
+    Some((start, expansion.clone()))
+}
+
+/// Finds where the word under the cursor starts and returns the words of the
+/// current command before it. Operators such as `|` or `;` start a new command,
+/// and so do reserved words like `then` or `do`.
+fn split_for_completion(line: &str) -> (usize, Vec<String>) {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '\\') => {
+                chars.next();
+            }
+            (None, ' ' | '\t' | '|' | ';' | '&' | '<' | '>' | '(' | ')' | '`') => {
+                if start < index {
+                    let word = &line[start..index];
+                    if !(words.is_empty() && starts_command(word)) {
+                        words.push(word.to_string());
+                    }
+                }
+                if c != ' ' && c != '\t' && !matches!(c, '<' | '>') {
+                    words.clear();
+                }
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    (start, words)
+}
+
+fn scan_path(path: &str) -> BTreeSet<String> {
+    path.split(':')
+        .filter(|dir| !dir.is_empty())
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| is_executable(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || "\\'\"$`&|;<>()*?[]{}!#".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn unescape(word: &str) -> String {
+    let mut out = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            '\'' | '"' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
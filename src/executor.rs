@@ -0,0 +1,801 @@
+use crate::chat;
+use crate::commands::{
+    abbreviations, background_jobs, change_dir, define_aliases, disown_jobs, export_vars,
+    foreground_job, get_os, history, list_jobs, local_vars, loop_control, print_env,
+    print_fortune, print_help, remove_aliases, return_from, unset_vars,
+    wait_jobs,
+};
+use crate::commands_enum::commands_enum::ShellCommand;
+use crate::expand::{expand_fields, expand_pattern, expand_word};
+use crate::glob::matches;
+use crate::history::state_dir;
+use crate::jobs::{enter_job, Group, Job, Stage};
+use crate::lexer::RedirectOp;
+use crate::parser::{parse, AndOr, Command, Compound, Connector, List, Pipeline, Redirect, SimpleCommand};
+use crate::search::search;
+use crate::shell::{parse_command, run_script, Flow, Shell};
+use crate::signals;
+use crate::streams::{HeldFds, Input, Io, Output};
+use crate::tree::tree;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::thread;
+use tokio::runtime::Handle;
+
+/// Runs a list with `io` as its standard streams, which is how the body of a
+/// redirected or piped control structure reaches the commands inside it.
+pub async fn run_list(list: &List, shell: &mut Shell, io: &Io) {
+    for and_or in &list.items {
+        if and_or.background {
+            run_in_background(and_or, shell, io).await;
+        } else {
+            run_and_or(and_or, shell, io).await;
+        }
+        if shell.interrupted() {
+            return;
+        }
+    }
+}
+
+/// Starts an and-or list ending in `&` as a job and returns right away. A
+/// lone pipeline becomes a job of its own stages; a longer list runs as a
+/// whole on a copy of the shell.
+async fn run_in_background(and_or: &AndOr, shell: &mut Shell, io: &Io) {
+    if and_or.rest.is_empty() {
+        start_pipeline(&and_or.first, shell, io, true).await;
+        return;
+    }
+
+    let io = match background_io(shell, io) {
+        Ok(io) => io,
+        Err(e) => {
+            eprintln!("heimdal: {e}");
+            shell.last_status = 1;
+            return;
+        }
+    };
+    let command = and_or.to_string();
+    let and_or = and_or.clone();
+    let mut subshell = shell.subshell();
+    let task = tokio::task::spawn_blocking(move || {
+        Handle::current().block_on(async {
+            run_and_or(&and_or, &mut subshell, &io).await;
+            subshell.last_status
+        })
+    });
+    let id = shell.jobs.add(Job::new(command, vec![Stage::Task(task)], None));
+    if shell.jobs.job_control() {
+        eprintln!("[{id}]");
+    }
+    shell.last_status = 0;
+}
+
+/// The streams of a background job. Without job control it must not read
+/// the terminal, so a plain stdin becomes `/dev/null`.
+fn background_io(shell: &Shell, io: &Io) -> io::Result<Io> {
+    let stdin = match io.stdin {
+        Input::Stdin if !shell.jobs.job_control() => Input::File(fs::File::open("/dev/null")?),
+        _ => io.stdin.try_clone()?,
+    };
+    Ok(Io {
+        stdin,
+        stdout: io.stdout.try_clone()?,
+        stderr: io.stderr.try_clone()?,
+    })
+}
+
+async fn run_and_or(and_or: &AndOr, shell: &mut Shell, io: &Io) {
+    run_pipeline(&and_or.first, shell, io).await;
+
+    for (connector, pipeline) in &and_or.rest {
+        if shell.interrupted() {
+            return;
+        }
+        let should_run = match connector {
+            Connector::And => shell.last_status == 0,
+            Connector::Or => shell.last_status != 0,
+        };
+        if should_run {
+            run_pipeline(pipeline, shell, io).await;
+        }
+    }
+}
+
+pub async fn run_pipeline(pipeline: &Pipeline, shell: &mut Shell, io: &Io) {
+    start_pipeline(pipeline, shell, io, false).await;
+}
+
+/// Starts every stage of a pipeline, wiring each stage's stdout to the next
+/// stage's stdin. In the foreground it waits and records the status of the
+/// last stage in `$?`; in the background the stages become a job.
+///
+/// A single built-in or control structure in the foreground runs against the
+/// shell itself. Otherwise they get a copy of the shell, like a subshell, and
+/// run on their own task so they can keep writing while the next stage reads.
+/// Under job control the external commands of a pipeline share a process
+/// group, which gets the terminal while it runs in the foreground.
+async fn start_pipeline(pipeline: &Pipeline, shell: &mut Shell, io: &Io, background: bool) {
+    let stage_count = pipeline.commands.len();
+    let in_shell = stage_count == 1 && !background;
+    let held_fds = shell.held_fds.mark();
+    let mut stdin = if background {
+        background_io(shell, io).map(|io| io.stdin)
+    } else {
+        io.stdin.try_clone()
+    };
+    let mut stages = Vec::new();
+    let mut last_builtin = None;
+    let mut pgid = None;
+
+    for (index, command) in pipeline.commands.iter().enumerate() {
+        let is_last = index + 1 == stage_count;
+        let (stdout, next_stdin) = if is_last {
+            (io.stdout.try_clone(), Ok(Input::Stdin))
+        } else {
+            match io::pipe() {
+                Ok((reader, writer)) => (Ok(Output::Pipe(writer)), Ok(Input::Pipe(reader))),
+                Err(e) => {
+                    eprintln!("heimdal: pipe: {e}");
+                    stages.push(Stage::Done(1));
+                    break;
+                }
+            }
+        };
+        let stage_io = (|| -> io::Result<Io> {
+            Ok(Io {
+                stdin: std::mem::replace(&mut stdin, next_stdin)?,
+                stdout: stdout?,
+                stderr: io.stderr.try_clone()?,
+            })
+        })();
+        let mut stage_io = match stage_io {
+            Ok(stage_io) => stage_io,
+            Err(e) => {
+                eprintln!("heimdal: {e}");
+                stages.push(Stage::Done(1));
+                continue;
+            }
+        };
+
+        let command = match command {
+            Command::Simple(command) => command,
+            Command::Function(name, body) => {
+                // Like assignments, a definition inside a pipeline would only
+                // reach a copy of the shell.
+                if in_shell {
+                    shell.functions.insert(name.clone(), body.clone());
+                }
+                stages.push(Stage::Done(0));
+                continue;
+            }
+            Command::Compound(compound, redirects) if in_shell => {
+                let status = run_compound(compound, redirects, shell, stage_io).await;
+                stages.push(Stage::Done(status));
+                continue;
+            }
+            Command::Compound(compound, redirects) => {
+                let (compound, redirects) = (compound.clone(), redirects.clone());
+                let mut subshell = shell.subshell();
+                stages.push(Stage::Task(tokio::task::spawn_blocking(move || {
+                    Handle::current().block_on(run_compound(
+                        &compound,
+                        &redirects,
+                        &mut subshell,
+                        stage_io,
+                    ))
+                })));
+                continue;
+            }
+        };
+
+        let command = match expand_command(command, shell) {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("heimdal: {e}");
+                stages.push(Stage::Done(1));
+                continue;
+            }
+        };
+        if let Err(e) = stage_io.apply_redirects(&command.redirects) {
+            eprintln!("heimdal: {e}");
+            stages.push(Stage::Done(1));
+            continue;
+        }
+        if command.words.is_empty() {
+            // Bare assignments set shell variables, unless they would only
+            // land in a pipeline's subshell.
+            if in_shell {
+                for (name, value) in command.assignments {
+                    shell.set_var(&name, value);
+                }
+            }
+            stages.push(Stage::Done(0));
+            continue;
+        }
+
+        if let Some(body) = shell.functions.get(&command.words[0]).cloned() {
+            let args = command.words[1..].to_vec();
+            if in_shell {
+                let status = call_function(&body, args, shell, stage_io).await;
+                stages.push(Stage::Done(status));
+            } else {
+                let mut subshell = shell.subshell();
+                stages.push(Stage::Task(tokio::task::spawn_blocking(move || {
+                    Handle::current().block_on(call_function(&body, args, &mut subshell, stage_io))
+                })));
+            }
+            continue;
+        }
+
+        match parse_command(command.words) {
+            ShellCommand::Unknown(cmd, args) => {
+                let group = shell.jobs.group(pgid, !background);
+                match spawn_external(&cmd, &args, &command.assignments, shell, stage_io, group) {
+                    Ok(pid) => {
+                        if let Some(group) = group {
+                            group.adopt(pid);
+                            pgid.get_or_insert(pid);
+                        }
+                        stages.push(Stage::Process(pid));
+                    }
+                    Err(status) => {
+                        if let Some(group) = group {
+                            group.abandon();
+                        }
+                        stages.push(Stage::Done(status));
+                    }
+                }
+            }
+            builtin if is_last && !background => last_builtin = Some((builtin, stage_io)),
+            builtin => {
+                let mut subshell = shell.subshell();
+                stages.push(Stage::Task(tokio::task::spawn_blocking(move || {
+                    let mut io = stage_io;
+                    Handle::current().block_on(run_builtin(builtin, &mut io, &mut subshell))
+                })));
+            }
+        }
+    }
+
+    if let Some((builtin, mut io)) = last_builtin {
+        let status = if in_shell {
+            run_builtin(builtin, &mut io, shell).await
+        } else {
+            run_builtin(builtin, &mut io, &mut shell.subshell()).await
+        };
+        stages.push(Stage::Done(status));
+    }
+
+    let job = Job::new(pipeline.to_string(), stages, pgid);
+    if background {
+        shell.held_fds.release(held_fds);
+        shell.last_background = job.last_pid();
+        let id = shell.jobs.add(job);
+        if shell.jobs.job_control() {
+            match shell.last_background {
+                Some(pid) => eprintln!("[{id}] {pid}"),
+                None => eprintln!("[{id}]"),
+            }
+        }
+        shell.last_status = 0;
+        return;
+    }
+
+    let mut status = shell.jobs.run_in_foreground(job).await;
+    shell.held_fds.release(held_fds);
+    if pipeline.negated {
+        status = i32::from(status == 0);
+    }
+    shell.last_status = status;
+}
+
+/// Runs `source` in a copy of the shell and returns what it wrote to stdout
+/// without trailing newlines, for `$(...)` and backquotes. Expansion is not
+/// async, so the current thread blocks until the commands finish.
+pub fn capture_output(source: &str, shell: &Shell) -> Result<String, String> {
+    let list = parse(source, &shell.aliases).map_err(|e| format!("syntax error: {e}"))?;
+    let (mut reader, writer) = io::pipe().map_err(|e| format!("pipe: {e}"))?;
+    // Read on another thread so a command with a lot of output never blocks
+    // on a full pipe.
+    let collector = thread::spawn(move || {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).map(|_| output)
+    });
+
+    if let Some(list) = list {
+        let io = Io {
+            stdin: Input::Stdin,
+            stdout: Output::Pipe(writer),
+            stderr: Output::Stderr,
+        };
+        let mut subshell = shell.subshell();
+        let handle = Handle::current();
+        tokio::task::block_in_place(|| handle.block_on(run_list(&list, &mut subshell, &io)));
+    } else {
+        drop(writer);
+    }
+
+    let output = collector
+        .join()
+        .map_err(|_| "command substitution failed".to_string())?
+        .map_err(|e| format!("command substitution: {e}"))?;
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+/// Starts `source` in a copy of the shell on its own task and returns a
+/// `/dev/fd` path to its stdout for `<(...)`, or to its stdin for `>(...)`.
+pub fn substitute_process(source: &str, reading: bool, shell: &Shell) -> Result<String, String> {
+    let list = parse(source, &shell.aliases).map_err(|e| format!("syntax error: {e}"))?;
+    let (reader, writer) = io::pipe().map_err(|e| format!("pipe: {e}"))?;
+    let (io, held) = if reading {
+        let io = Io {
+            stdin: Input::Stdin,
+            stdout: Output::Pipe(writer),
+            stderr: Output::Stderr,
+        };
+        (io, reader.into())
+    } else {
+        let io = Io {
+            stdin: Input::Pipe(reader),
+            stdout: Output::Stdout,
+            stderr: Output::Stderr,
+        };
+        (io, writer.into())
+    };
+    let path = shell.held_fds.hold(held);
+
+    if let Some(list) = list {
+        let mut subshell = shell.subshell();
+        // Holding the other end here would keep the pipe from ever closing.
+        subshell.held_fds = HeldFds::default();
+        tokio::task::spawn_blocking(move || {
+            Handle::current().block_on(run_list(&list, &mut subshell, &io))
+        });
+    }
+    Ok(path)
+}
+
+/// Runs a control structure or block with its redirects applied on top of
+/// `io`, and returns its status.
+async fn run_compound(
+    compound: &Compound,
+    redirects: &[Redirect],
+    shell: &mut Shell,
+    mut io: Io,
+) -> i32 {
+    let applied = expand_redirects(redirects, shell)
+        .and_then(|redirects| io.apply_redirects(&redirects).map_err(|e| e.to_string()));
+    if let Err(e) = applied {
+        eprintln!("heimdal: {e}");
+        return 1;
+    }
+    // Boxed because the lists inside recurse back into `run_list`.
+    Box::pin(run_compound_body(compound, shell, &io)).await;
+    shell.last_status
+}
+
+async fn run_compound_body(compound: &Compound, shell: &mut Shell, io: &Io) {
+    match compound {
+        Compound::Group(list) => run_list(list, shell, io).await,
+        Compound::Subshell(list) => {
+            let mut subshell = shell.subshell();
+            run_list(list, &mut subshell, io).await;
+            shell.last_status = subshell.last_status;
+        }
+        Compound::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches {
+                run_list(condition, shell, io).await;
+                if shell.interrupted() {
+                    return;
+                }
+                if shell.last_status == 0 {
+                    run_list(body, shell, io).await;
+                    return;
+                }
+            }
+            match otherwise {
+                Some(body) => run_list(body, shell, io).await,
+                None => shell.last_status = 0,
+            }
+        }
+        Compound::While {
+            condition,
+            body,
+            until,
+        } => {
+            shell.loop_depth += 1;
+            let mut status = 0;
+            loop {
+                run_list(condition, shell, io).await;
+                if leave_loop(shell) || (shell.last_status == 0) == *until {
+                    break;
+                }
+                run_list(body, shell, io).await;
+                status = shell.last_status;
+                if leave_loop(shell) {
+                    break;
+                }
+            }
+            shell.loop_depth -= 1;
+            shell.last_status = status;
+        }
+        Compound::For { name, words, body } => {
+            let values = match words {
+                Some(words) => {
+                    let fields = words
+                        .iter()
+                        .map(|word| expand_fields(word, shell))
+                        .collect::<Result<Vec<_>, _>>();
+                    match fields {
+                        Ok(fields) => fields.concat(),
+                        Err(e) => {
+                            eprintln!("heimdal: {e}");
+                            shell.last_status = 1;
+                            return;
+                        }
+                    }
+                }
+                None => shell.args.clone(),
+            };
+
+            shell.loop_depth += 1;
+            shell.last_status = 0;
+            for value in values {
+                shell.set_var(name, value);
+                run_list(body, shell, io).await;
+                if leave_loop(shell) {
+                    break;
+                }
+            }
+            shell.loop_depth -= 1;
+        }
+        Compound::Case { word, arms } => {
+            let value = match expand_word(word, shell) {
+                Ok(value) => value,
+                Err(e) => {
+                    eprintln!("heimdal: {e}");
+                    shell.last_status = 1;
+                    return;
+                }
+            };
+            shell.last_status = 0;
+            for arm in arms {
+                for pattern in &arm.patterns {
+                    match expand_pattern(pattern, shell) {
+                        Ok(pattern) if matches(&pattern, &value) => {
+                            run_list(&arm.body, shell, io).await;
+                            return;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("heimdal: {e}");
+                            shell.last_status = 1;
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs a function body with `args` as its positional parameters and its own
+/// scope for `local` variables, and returns its status.
+async fn call_function(body: &List, args: Vec<String>, shell: &mut Shell, io: Io) -> i32 {
+    let saved_args = std::mem::replace(&mut shell.args, args);
+    // Loops around the call are out of reach of `break` inside the function.
+    let saved_loop_depth = std::mem::take(&mut shell.loop_depth);
+    shell.call_depth += 1;
+    shell.push_scope();
+
+    // Boxed because the body recurses back into `run_list`.
+    Box::pin(run_list(body, shell, &io)).await;
+    if shell.flow == Flow::Return {
+        shell.flow = Flow::Normal;
+    }
+
+    shell.pop_scope();
+    shell.call_depth -= 1;
+    shell.loop_depth = saved_loop_depth;
+    shell.args = saved_args;
+    shell.last_status
+}
+
+/// Handles a pending `break` or `continue` at the end of a loop iteration and
+/// reports whether the loop has to stop.
+fn leave_loop(shell: &mut Shell) -> bool {
+    match shell.flow {
+        Flow::Normal => shell.exit_requested || signals::interrupted(),
+        Flow::Return => true,
+        Flow::Break(count) => {
+            shell.flow = if count > 1 {
+                Flow::Break(count - 1)
+            } else {
+                Flow::Normal
+            };
+            true
+        }
+        Flow::Continue(count) if count > 1 => {
+            shell.flow = Flow::Continue(count - 1);
+            true
+        }
+        Flow::Continue(_) => {
+            shell.flow = Flow::Normal;
+            false
+        }
+    }
+}
+
+/// Returns a copy of `command` with every word expanded.
+fn expand_command(command: &SimpleCommand, shell: &Shell) -> Result<SimpleCommand, String> {
+    let assignments = command
+        .assignments
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), expand_word(value, shell)?)))
+        .collect::<Result<_, String>>()?;
+    let words = command
+        .words
+        .iter()
+        .map(|word| expand_fields(word, shell))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+
+    Ok(SimpleCommand {
+        assignments,
+        words,
+        redirects: expand_redirects(&command.redirects, shell)?,
+    })
+}
+
+/// Expands redirect targets, each of which must name exactly one file.
+fn expand_redirects(redirects: &[Redirect], shell: &Shell) -> Result<Vec<Redirect>, String> {
+    redirects
+        .iter()
+        .map(|redirect| {
+            let mut targets = expand_fields(&redirect.target, shell)?;
+            if targets.len() != 1 {
+                return Err(format!("{}: ambiguous redirect", redirect.target));
+            }
+            let target = targets.remove(0);
+            let target = match redirect.op {
+                RedirectOp::Duplicate => target,
+                _ => shell.resolve(&target).display().to_string(),
+            };
+            Ok(Redirect { target, ..*redirect })
+        })
+        .collect()
+}
+
+/// Spawns an external command with the shell's exported variables plus any
+/// `NAME=value` prefixes, in `group` under job control. Returns its process
+/// id, or the status to report if it could not be started.
+fn spawn_external(
+    cmd: &str,
+    args: &[String],
+    assignments: &[(String, String)],
+    shell: &Shell,
+    io: Io,
+    group: Option<Group>,
+) -> Result<i32, i32> {
+    let mut command = process::Command::new(if cmd.contains('/') {
+        shell.resolve(cmd)
+    } else {
+        cmd.into()
+    });
+    if let Some(dir) = shell.own_dir() {
+        command.current_dir(dir);
+    }
+    let held_fds = shell.held_fds.raw_fds();
+    // SAFETY: the hook runs in the forked child, where it only makes
+    // async-signal-safe calls.
+    unsafe {
+        command.pre_exec(move || {
+            // Let the command open `/dev/fd` paths from `<(...)` and `>(...)`.
+            for &fd in &held_fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            enter_job(group);
+            Ok(())
+        });
+    }
+    command
+        .args(args)
+        .env_clear()
+        .envs(shell.exported_vars())
+        .envs(assignments.iter().map(|(name, value)| (name, value)))
+        .stdin(io.stdin.into_stdio())
+        .stdout(io.stdout.into_stdio())
+        .stderr(io.stderr.into_stdio())
+        .spawn()
+        .map(|child| child.id() as i32)
+        .map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                eprintln!("heimdal: command not found: {cmd}");
+                127
+            } else {
+                eprintln!("heimdal: {cmd}: {e}");
+                126
+            }
+        })
+}
+
+/// Runs a built-in and returns its exit status.
+async fn run_builtin(command: ShellCommand, io: &mut Io, shell: &mut Shell) -> i32 {
+    let Io {
+        stdin,
+        stdout: out,
+        stderr: err,
+    } = io;
+    let result = match command {
+        ShellCommand::Exit(code) => {
+            shell.exit_requested = true;
+            match code.as_deref().map(|code| (code, code.parse::<i32>())) {
+                None => Ok(shell.last_status),
+                Some((_, Ok(code))) => Ok(code & 0xff),
+                Some((code, Err(_))) => {
+                    writeln!(err, "exit: {code}: numeric argument required").map(|_| 2)
+                }
+            }
+        }
+        ShellCommand::Clear => write!(out, "\x1B[2J\x1B[1;1H").map(|_| 0),
+        ShellCommand::Os => {
+            let os = get_os();
+            if os == "windows" {
+                writeln!(out, "You're running on Windows.").map(|_| 0)
+            } else {
+                writeln!(out, "You're running on Linux.").map(|_| 0)
+            }
+        }
+        ShellCommand::Tree(args) => {
+            let colour = out.is_terminal();
+            tree(&args, shell.base_dir(), colour, shell.var("LS_COLORS"), out, err)
+        }
+        ShellCommand::Tip => print_fortune(out).map(|_| 0),
+        ShellCommand::Search(args) => {
+            let colour = out.is_terminal();
+            let journal = state_dir(|name| shell.var(name).map(str::to_string))
+                .map(|dir| dir.join("replace"));
+            search(&args, shell.base_dir(), colour, journal, stdin, out, err).await
+        }
+        ShellCommand::Cd(dir) => change_dir(dir.as_deref(), shell, out, err),
+        ShellCommand::Whoami => writeln!(out, "{}", shell.user()).map(|_| 0),
+        ShellCommand::Export(args) => export_vars(&args, shell, out, err),
+        ShellCommand::Unset(names) => unset_vars(&names, shell, err),
+        ShellCommand::Env => print_env(shell, out).map(|_| 0),
+        ShellCommand::History(args) => history(&args, shell, out, err),
+        ShellCommand::Alias(args) => define_aliases(&args, shell, out, err),
+        ShellCommand::Unalias(args) => remove_aliases(&args, shell, err),
+        ShellCommand::Abbr(args) => abbreviations(&args, shell, out, err),
+        ShellCommand::Source(args) => source(&args, shell, stdin, out, err).await,
+        ShellCommand::Break(count) => {
+            loop_control("break", count.as_deref(), Flow::Break, shell, err)
+        }
+        ShellCommand::Local(args) => local_vars(&args, shell, err),
+        ShellCommand::Return(code) => return_from(code.as_deref(), shell, err),
+        ShellCommand::Jobs(args) => list_jobs(&args, shell, out, err).await,
+        ShellCommand::Fg(spec) => foreground_job(spec.as_deref(), shell, out, err).await,
+        ShellCommand::Bg(specs) => background_jobs(&specs, shell, err),
+        ShellCommand::Wait(args) => wait_jobs(&args, shell, err).await,
+        ShellCommand::Disown(args) => disown_jobs(&args, shell, err),
+        ShellCommand::Continue(count) => {
+            loop_control("continue", count.as_deref(), Flow::Continue, shell, err)
+        }
+        ShellCommand::HeimdalHelp => print_help(out).map(|_| 0),
+        ShellCommand::Chat(config) => match chat::start_chat(config) {
+            Err(e) => writeln!(err, "Chat error: {}", e).map(|_| 1),
+            Ok(()) => Ok(0),
+        },
+        ShellCommand::Unknown(..) => Ok(0),
+    };
+
+    match result.and_then(|status| out.flush().map(|_| status)) {
+        Ok(status) => status,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                let _ = writeln!(err, "heimdal: {e}");
+            }
+            1
+        }
+    }
+}
+
+/// `source file` runs a script in the current shell, with the built-in's
+/// streams as the script's standard streams.
+async fn source(
+    args: &[String],
+    shell: &mut Shell,
+    stdin: &Input,
+    out: &Output,
+    err: &mut Output,
+) -> io::Result<i32> {
+    let Some(path) = args.first() else {
+        writeln!(err, "source: filename argument required")?;
+        return Ok(2);
+    };
+    let script = match fs::read_to_string(shell.resolve(path)) {
+        Ok(script) => script,
+        Err(e) => {
+            writeln!(err, "source: {path}: {e}")?;
+            return Ok(1);
+        }
+    };
+
+    let io = Io {
+        stdin: stdin.try_clone()?,
+        stdout: out.try_clone()?,
+        stderr: err.try_clone()?,
+    };
+    shell.call_depth += 1;
+    run_script(&script, path, shell, &io).await;
+    shell.call_depth -= 1;
+    if shell.flow == Flow::Return {
+        shell.flow = Flow::Normal;
+    }
+    Ok(shell.last_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::ptr;
+
+    const IN_TERMINAL: &str = "HEIMDAL_TEST_IN_TERMINAL";
+
+    // Job control needs a controlling terminal, so the check runs in a copy
+    // of the test binary started in a session of its own on a fresh pty.
+    #[test]
+    fn a_missing_command_leaves_the_terminal_with_the_shell() {
+        if env::var_os(IN_TERMINAL).is_some() {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let shell = runtime.block_on(async {
+                let mut shell = Shell::new();
+                shell.jobs.enable_job_control().unwrap();
+                let list = parse("heimdal-no-such-command", &Default::default()).unwrap().unwrap();
+                run_list(&list, &mut shell, &Io::inherit()).await;
+                shell
+            });
+            assert_eq!(shell.last_status, 127);
+            // SAFETY: plain system calls on stdin and this process.
+            assert_eq!(unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) }, unsafe { libc::getpgrp() });
+            return;
+        }
+
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: `openpty` fills in two fresh descriptors, owned from here.
+        let (_master, slave) = unsafe {
+            assert_eq!(libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), ptr::null()), 0);
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        };
+        let mut command = process::Command::new(env::current_exe().unwrap());
+        // SAFETY: the hook runs in the forked child and only makes
+        // async-signal-safe calls.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let output = command
+            .args(["--exact", "executor::tests::a_missing_command_leaves_the_terminal_with_the_shell"])
+            .env(IN_TERMINAL, "1")
+            .stdin(slave.try_clone().unwrap())
+            .stdout(process::Stdio::piped())
+            .stderr(slave)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+}
@@ -0,0 +1,599 @@
+use crate::executor::{capture_output, substitute_process};
+use crate::glob::glob;
+use crate::shell::Shell;
+use std::ffi::{CStr, CString};
+use std::iter::Peekable;
+use std::process;
+use std::str::Chars;
+
+/// Fully expands a raw command word into the arguments it stands for: brace
+/// expansion, tilde, parameters, quote removal and then filename globbing. Only
+/// wildcards written outside quotes are expanded, and a pattern matching
+/// nothing is left as typed.
+pub fn expand_fields(word: &str, shell: &Shell) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    for field in expand_braces(word)
+        .iter()
+        .map(|word| expand(word, shell, true))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+    {
+        let matches = if field.has_wildcards {
+            glob(&field.pattern, shell.base_dir())
+        } else {
+            Vec::new()
+        };
+        if matches.is_empty() {
+            fields.push(field.text);
+        } else {
+            fields.extend(matches);
+        }
+    }
+    Ok(fields)
+}
+
+/// Expands parameters in a raw word from the lexer and removes its quotes and
+/// escapes, without splitting it into several arguments.
+pub fn expand_word(word: &str, shell: &Shell) -> Result<String, String> {
+    let fields = expand(word, shell, false)?;
+    Ok(fields
+        .into_iter()
+        .map(|field| field.text)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Expands a `case` pattern. Wildcards written outside quotes keep their
+/// meaning while quoted ones are escaped to match literally.
+pub fn expand_pattern(word: &str, shell: &Shell) -> Result<String, String> {
+    let fields = expand(word, shell, false)?;
+    Ok(fields
+        .into_iter()
+        .map(|field| field.pattern)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// An expanded word, along with the glob pattern it spells when quoted
+/// characters are escaped.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    has_wildcards: bool,
+}
+
+impl Field {
+    fn push_quoted(&mut self, c: char) {
+        self.text.push(c);
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+
+    fn push_quoted_str(&mut self, s: &str) {
+        s.chars().for_each(|c| self.push_quoted(c));
+    }
+
+    fn push_unquoted(&mut self, c: char) {
+        self.text.push(c);
+        self.pattern.push(c);
+        self.has_wildcards |= matches!(c, '*' | '?' | '[');
+    }
+}
+
+/// Expands one word into fields. `$@` yields one field per positional
+/// parameter, and none at all for a `"$@"` with no parameters. With `split`,
+/// an unquoted command substitution yields one per word of its output.
+fn expand(word: &str, shell: &Shell, split: bool) -> Result<Vec<Field>, String> {
+    let mut fields = vec![Field::default()];
+    let mut empty_list = false;
+    let word = match expand_tilde(word, shell) {
+        Some((home, rest)) => {
+            current(&mut fields).push_quoted_str(&home);
+            rest
+        }
+        None => word,
+    };
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\n') | None => {}
+                Some(next) => current(&mut fields).push_quoted(next),
+            },
+            '\'' => {
+                for ch in chars.by_ref() {
+                    if ch == '\'' {
+                        break;
+                    }
+                    current(&mut fields).push_quoted(ch);
+                }
+            }
+            '"' => {
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '"' => break,
+                        // Inside double quotes a backslash only escapes a few characters.
+                        '\\' => match chars.next() {
+                            Some(next @ ('"' | '\\' | '$' | '`')) => current(&mut fields).push_quoted(next),
+                            Some('\n') => {}
+                            Some(next) => {
+                                current(&mut fields).push_quoted('\\');
+                                current(&mut fields).push_quoted(next);
+                            }
+                            None => current(&mut fields).push_quoted('\\'),
+                        },
+                        '$' => {
+                            let values = expand_parameter(&mut chars, shell, true)?;
+                            empty_list |= values.is_empty();
+                            push_values(&mut fields, values);
+                        }
+                        '`' => {
+                            let command = take_backquoted(&mut chars)?;
+                            current(&mut fields).push_quoted_str(&capture_output(&command, shell)?);
+                        }
+                        _ => current(&mut fields).push_quoted(ch),
+                    }
+                }
+            }
+            '$' => {
+                let values = expand_parameter(&mut chars, shell, !split)?;
+                empty_list |= values.is_empty();
+                push_values(&mut fields, values);
+            }
+            '`' => {
+                let command = take_backquoted(&mut chars)?;
+                let values = split_output(capture_output(&command, shell)?, !split);
+                empty_list |= values.is_empty();
+                push_values(&mut fields, values);
+            }
+            // The lexer only leaves an unquoted `<(` or `>(` in a word for a
+            // process substitution.
+            '<' | '>' if chars.peek() == Some(&'(') => {
+                chars.next();
+                let command = take_parenthesized(&mut chars)?;
+                let path = substitute_process(&command, c == '<', shell)?;
+                current(&mut fields).push_quoted_str(&path);
+            }
+            _ => current(&mut fields).push_unquoted(c),
+        }
+    }
+
+    if empty_list && fields.len() == 1 && fields[0].text.is_empty() {
+        fields.clear();
+    }
+    Ok(fields)
+}
+
+fn current(fields: &mut [Field]) -> &mut Field {
+    fields.last_mut().expect("fields is never empty while expanding")
+}
+
+/// Appends parameter values, which are never globbed. Each value after the
+/// first starts a new field.
+fn push_values(fields: &mut Vec<Field>, values: Vec<String>) {
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            fields.push(Field::default());
+        }
+        current(fields).push_quoted_str(value);
+    }
+}
+
+/// Expands a leading `~` or `~user`, returning the home directory and the rest
+/// of the word. The prefix must be unquoted and end at the first `/`.
+fn expand_tilde<'a>(word: &'a str, shell: &Shell) -> Option<(String, &'a str)> {
+    let prefix = word.strip_prefix('~')?;
+    let end = prefix.find('/').unwrap_or(prefix.len());
+    let (user, rest) = prefix.split_at(end);
+    if user.contains(['\'', '"', '\\', '$']) {
+        return None;
+    }
+
+    let home = if user.is_empty() {
+        shell.var("HOME")?.to_string()
+    } else {
+        home_dir_of(user)?
+    };
+    Some((home, rest))
+}
+
+/// Looks up another user's home directory in the password database.
+pub fn home_dir_of(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    // SAFETY: `getpwnam` returns either null or a pointer to a static record
+    // whose `pw_dir` is copied out before any other call can overwrite it.
+    unsafe {
+        let entry = libc::getpwnam(name.as_ptr());
+        if entry.is_null() || (*entry).pw_dir.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*entry).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Expands `{a,b}` alternatives outside quotes, so `file.{rs,toml}` becomes
+/// `file.rs file.toml`. Words without a complete brace list are returned as is.
+pub fn expand_braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let Some((open, commas, close)) = find_brace_list(&chars) else {
+        return vec![word.to_string()];
+    };
+
+    let prefix: String = chars[..open].iter().collect();
+    let suffix: String = chars[close + 1..].iter().collect();
+    let mut bounds = vec![open];
+    bounds.extend(&commas);
+    bounds.push(close);
+
+    bounds
+        .windows(2)
+        .flat_map(|pair| {
+            let alternative: String = chars[pair[0] + 1..pair[1]].iter().collect();
+            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
+
+// Finds the first unquoted `{` that has a matching `}` and at least one comma
+// at its own nesting level, skipping `${...}` parameters and `$(...)`.
+fn find_brace_list(chars: &[char]) -> Option<(usize, Vec<usize>, usize)> {
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '\'' | '"' => index = skip_quoted(chars, index),
+            '{' | '(' if index > 0 && chars[index - 1] == '$' => {
+                index = find_closing(chars, index).unwrap_or(chars.len());
+            }
+            '{' => {
+                if let Some(close) = find_closing(chars, index) {
+                    let commas = top_level_commas(chars, index, close);
+                    if !commas.is_empty() {
+                        return Some((index, commas, close));
+                    }
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut index = start + 1;
+    while index < chars.len() && chars[index] != quote {
+        if quote == '"' && chars[index] == '\\' {
+            index += 1;
+        }
+        index += 1;
+    }
+    index
+}
+
+// Finds the `}` or `)` matching the bracket at `open`.
+fn find_closing(chars: &[char], open: usize) -> Option<usize> {
+    let (opening, closing) = if chars[open] == '(' { ('(', ')') } else { ('{', '}') };
+    let mut depth = 0;
+    let mut index = open;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '\'' | '"' => index = skip_quoted(chars, index),
+            ch if ch == opening => depth += 1,
+            ch if ch == closing => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+fn top_level_commas(chars: &[char], open: usize, close: usize) -> Vec<usize> {
+    let mut commas = Vec::new();
+    let mut depth = 0;
+    let mut index = open + 1;
+    while index < close {
+        match chars[index] {
+            '\\' => index += 1,
+            '\'' | '"' => index = skip_quoted(chars, index),
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(index),
+            _ => {}
+        }
+        index += 1;
+    }
+    commas
+}
+
+// Called right after a `$`. `$@` and `${@}` give one value per positional
+// parameter and `$(...)` one per word of its output unless quoted; every other
+// parameter gives a single value.
+fn expand_parameter(
+    chars: &mut Peekable<Chars>,
+    shell: &Shell,
+    quoted: bool,
+) -> Result<Vec<String>, String> {
+    if chars.next_if_eq(&'(').is_some() {
+        let command = take_parenthesized(chars)?;
+        return Ok(split_output(capture_output(&command, shell)?, quoted));
+    }
+    let mut ahead = chars.clone();
+    if ahead.next_if_eq(&'{').is_some() && ahead.next() == Some('@') && ahead.next() == Some('}') {
+        *chars = ahead;
+        return Ok(shell.args.clone());
+    }
+    if chars.next_if_eq(&'@').is_some() {
+        return Ok(shell.args.clone());
+    }
+    expand_scalar(chars, shell).map(|value| vec![value])
+}
+
+/// Splits unquoted command output into words on whitespace, so `$(ls)` gives
+/// one argument per name. Quoted output stays a single argument.
+fn split_output(output: String, quoted: bool) -> Vec<String> {
+    if quoted {
+        vec![output]
+    } else {
+        output.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+// Called right after the `(` of `$(`, `<(` or `>(`; returns the command up to
+// the matching `)`, which the lexer has already checked is there.
+fn take_parenthesized(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut command = String::new();
+    let mut depth = 1;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(command);
+                }
+            }
+            '\\' => {
+                command.push(ch);
+                if let Some(next) = chars.next() {
+                    command.push(next);
+                }
+                continue;
+            }
+            '\'' | '"' => {
+                command.push(ch);
+                while let Some(inner) = chars.next() {
+                    command.push(inner);
+                    if inner == '\\' && ch == '"' {
+                        command.extend(chars.next());
+                    } else if inner == ch {
+                        break;
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        command.push(ch);
+    }
+    Err("unterminated (".to_string())
+}
+
+// Called right after an opening backquote. Inside, a backslash only escapes
+// another backquote, a backslash or `$`.
+fn take_backquoted(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut command = String::new();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '`' => return Ok(command),
+            '\\' => match chars.next() {
+                Some(next @ ('`' | '\\' | '$')) => command.push(next),
+                Some(next) => {
+                    command.push('\\');
+                    command.push(next);
+                }
+                None => command.push('\\'),
+            },
+            _ => command.push(ch),
+        }
+    }
+    Err("unterminated ` quote".to_string())
+}
+
+// A `$` that does not start a parameter stays literal.
+fn expand_scalar(chars: &mut Peekable<Chars>, shell: &Shell) -> Result<String, String> {
+    match chars.peek() {
+        Some('?') => {
+            chars.next();
+            Ok(shell.last_status.to_string())
+        }
+        Some('$') => {
+            chars.next();
+            Ok(process::id().to_string())
+        }
+        Some('#') => {
+            chars.next();
+            Ok(shell.args.len().to_string())
+        }
+        Some('!') => {
+            chars.next();
+            Ok(shell.last_background.map(|pid| pid.to_string()).unwrap_or_default())
+        }
+        Some('*') => {
+            chars.next();
+            Ok(shell.args.join(" "))
+        }
+        Some(&c) if c.is_ascii_digit() => {
+            chars.next();
+            let index = c.to_digit(10).unwrap_or_default() as usize;
+            Ok(shell.positional(index).unwrap_or_default().to_string())
+        }
+        Some('{') => {
+            chars.next();
+            let mut inner = String::new();
+            let mut depth = 1;
+            for ch in chars.by_ref() {
+                match ch {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                inner.push(ch);
+            }
+            expand_braced(&inner, shell)
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            let mut name = String::new();
+            while let Some(ch) = chars.next_if(|ch| ch.is_ascii_alphanumeric() || *ch == '_') {
+                name.push(ch);
+            }
+            Ok(shell.var(&name).unwrap_or_default().to_string())
+        }
+        _ => Ok("$".to_string()),
+    }
+}
+
+/// Expands the inside of `${...}`: a plain name or position such as `${10}`,
+/// `NAME:-default` when unset or empty, or `NAME-default` when unset.
+fn expand_braced(inner: &str, shell: &Shell) -> Result<String, String> {
+    match inner {
+        "?" => return Ok(shell.last_status.to_string()),
+        "#" => return Ok(shell.args.len().to_string()),
+        "*" => return Ok(shell.args.join(" ")),
+        _ => {}
+    }
+
+    let name_end = inner
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(inner.len());
+    let (name, rest) = inner.split_at(name_end);
+    if name.is_empty() {
+        return Err(format!("${{{inner}}}: bad substitution"));
+    }
+
+    let value = match name.parse::<usize>() {
+        Ok(index) => shell.positional(index),
+        Err(_) => shell.var(name),
+    };
+    if rest.is_empty() {
+        return Ok(value.unwrap_or_default().to_string());
+    }
+    if let Some(default) = rest.strip_prefix(":-") {
+        return match value {
+            Some(value) if !value.is_empty() => Ok(value.to_string()),
+            _ => expand_word(default, shell),
+        };
+    }
+    if let Some(default) = rest.strip_prefix('-') {
+        return match value {
+            Some(value) => Ok(value.to_string()),
+            None => expand_word(default, shell),
+        };
+    }
+
+    Err(format!("${{{inner}}}: bad substitution"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> Shell {
+        let mut shell = Shell::new();
+        shell.set_var("NAME", "world".to_string());
+        shell.set_var("EMPTY", String::new());
+        shell.set_var("SPACED", "a  b".to_string());
+        shell
+    }
+
+    fn fields(word: &str, shell: &Shell) -> Vec<String> {
+        expand_fields(word, shell).unwrap()
+    }
+
+    #[test]
+    fn parameters_and_defaults() {
+        let shell = shell();
+        assert_eq!(expand_word("hello $NAME", &shell).unwrap(), "hello world");
+        assert_eq!(expand_word("${NAME}s", &shell).unwrap(), "worlds");
+        assert_eq!(expand_word("${HEIMDAL_TEST_UNSET:-d}", &shell).unwrap(), "d");
+        assert_eq!(expand_word("${EMPTY:-d}", &shell).unwrap(), "d");
+        assert_eq!(expand_word("${EMPTY-d}", &shell).unwrap(), "");
+        assert_eq!(expand_word("${HEIMDAL_TEST_UNSET-d}", &shell).unwrap(), "d");
+        assert_eq!(expand_word("${NAME:-d}", &shell).unwrap(), "world");
+        assert_eq!(expand_word("${HEIMDAL_TEST_UNSET:-$NAME x}", &shell).unwrap(), "world x");
+        assert_eq!(expand_word("$HEIMDAL_TEST_UNSET", &shell).unwrap(), "");
+    }
+
+    #[test]
+    fn quotes_decide_what_expands() {
+        let shell = shell();
+        assert_eq!(fields(r#"'$NAME'-"$NAME"-\$NAME"#, &shell), ["$NAME-world-$NAME"]);
+        assert_eq!(fields("$SPACED", &shell), ["a  b"]);
+        assert_eq!(fields("\"a  b\"", &shell), ["a  b"]);
+        assert_eq!(expand_word(r#""a \x \$""#, &shell).unwrap(), r"a \x $");
+    }
+
+    #[test]
+    fn brace_lists() {
+        assert_eq!(expand_braces("file.{rs,toml}"), ["file.rs", "file.toml"]);
+        assert_eq!(expand_braces("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("x{a,{b,c}}"), ["xa", "xb", "xc"]);
+        assert_eq!(expand_braces("{a,}z"), ["az", "z"]);
+        for word in ["{a}", "{a,b", "'{a,b}'", "\\{a,b}", "${X:-a,b}", "$(echo {a,b})"] {
+            assert_eq!(expand_braces(word), [word]);
+        }
+        assert_eq!(fields("\"{a,b}\"{c,d}", &shell()), ["{a,b}c", "{a,b}d"]);
+    }
+
+    #[test]
+    fn tilde_prefixes() {
+        let mut shell = shell();
+        shell.set_var("HOME", "/home/me".to_string());
+        assert_eq!(fields("~", &shell), ["/home/me"]);
+        assert_eq!(fields("~/src", &shell), ["/home/me/src"]);
+        assert_eq!(fields("~root/x", &shell), [format!("{}/x", home_dir_of("root").unwrap())]);
+        for (word, expanded) in [("a~", "a~"), ("'~'", "~"), ("\\~", "~"), ("~heimdal-no-such-user", "~heimdal-no-such-user")] {
+            assert_eq!(fields(word, &shell), [expanded]);
+        }
+        // The home directory is taken literally, even with glob characters.
+        shell.set_var("HOME", "/no/such/[dir]*".to_string());
+        assert_eq!(fields("~", &shell), ["/no/such/[dir]*"]);
+    }
+
+    #[test]
+    fn positional_parameters() {
+        let mut shell = shell();
+        shell.args = ["a b", "c", "d", "e", "f", "g", "h", "i", "j", "k"].map(String::from).to_vec();
+        assert_eq!(fields("\"$@\"", &shell), shell.args);
+        assert_eq!(fields("\"x${@}y\"", &shell)[..2], ["xa b", "c"]);
+        assert_eq!(fields("\"$@\"", &shell).last().unwrap(), "k");
+        assert_eq!(expand_word("$# $1 ${10} $11", &shell).unwrap(), "10 a b k a b1");
+        assert_eq!(fields("\"$*\"", &shell), ["a b c d e f g h i j k"]);
+
+        shell.args.clear();
+        assert!(fields("\"$@\"", &shell).is_empty());
+        assert_eq!(fields("\"x$@\"", &shell), ["x"]);
+        assert_eq!(expand_word("$# [$1]", &shell).unwrap(), "0 []");
+    }
+}
@@ -0,0 +1,254 @@
+use globset::{GlobBuilder, GlobMatcher};
+use std::fs;
+use std::path::Path;
+
+/// Whether `pattern` contains an unescaped `*`, `?` or `[`.
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Matches a single name against a pattern made of `*`, `?`, `[abc]`,
+/// `[!a-z]` and backslash escapes. A pattern that is not valid, such as one
+/// with an unclosed `[`, only matches itself.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    Pattern::new(pattern).matches(name)
+}
+
+/// A pattern compiled once to be matched against many names.
+pub struct Pattern {
+    matcher: Option<GlobMatcher>,
+    literal: String,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let matcher = GlobBuilder::new(&escape_braces(pattern))
+            .literal_separator(false)
+            .backslash_escape(true)
+            .build()
+            .ok()
+            .map(|glob| glob.compile_matcher());
+        Pattern {
+            matcher,
+            literal: unescape(pattern),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.matcher {
+            Some(matcher) => matcher.is_match(name),
+            None => name == self.literal,
+        }
+    }
+}
+
+// Braces were already expanded by the shell, so any left in a pattern are
+// plain characters rather than globset alternatives.
+fn escape_braces(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                out.extend(chars.next());
+            }
+            '[' if !in_class => {
+                in_class = true;
+                out.push(c);
+                // A `]` first in the class, after any `!` or `^`, is literal.
+                if let Some(negation) = chars.next_if(|&c| c == '!' || c == '^') {
+                    out.push(negation);
+                }
+                out.extend(chars.next_if_eq(&']'));
+            }
+            ']' if in_class => {
+                in_class = false;
+                out.push(c);
+            }
+            '{' | '}' if !in_class => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Expands a path pattern against the file system, returning the sorted
+/// matches. A `**` component matches any number of nested directories.
+/// Relative patterns are looked up from `cwd` but matched paths stay
+/// relative.
+pub fn glob(pattern: &str, cwd: &Path) -> Vec<String> {
+    let (mut candidates, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').filter(|part| !part.is_empty()).collect();
+
+    for (index, component) in components.iter().enumerate() {
+        let is_last = index + 1 == components.len();
+        let mut next = Vec::new();
+
+        let pattern = Pattern::new(component);
+        for prefix in &candidates {
+            if *component == "**" {
+                next.push(prefix.clone());
+                collect_recursive(prefix, cwd, is_last, &mut next);
+            } else if has_wildcards(component) {
+                let Ok(entries) = fs::read_dir(cwd.join(dir_of(prefix))) else {
+                    continue;
+                };
+                for entry in entries.filter_map(Result::ok) {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    // Hidden files only match a pattern that spells out the dot.
+                    let hidden = name.starts_with('.') && !component.starts_with('.');
+                    if !hidden && pattern.matches(&name) {
+                        next.push(join(prefix, &name));
+                    }
+                }
+            } else {
+                next.push(join(prefix, &unescape(component)));
+            }
+        }
+
+        candidates = next;
+    }
+
+    let mut found: Vec<String> = candidates
+        .into_iter()
+        .filter(|path| !path.is_empty() && cwd.join(path).exists())
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+// Adds every non-hidden directory below `prefix`, and files too when `**` ends
+// the pattern.
+fn collect_recursive(prefix: &str, cwd: &Path, include_files: bool, found: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(cwd.join(dir_of(prefix))) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(prefix, &name);
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            found.push(path.clone());
+            collect_recursive(&path, cwd, include_files, found);
+        } else if include_files {
+            found.push(path);
+        }
+    }
+}
+
+fn dir_of(prefix: &str) -> &str {
+    if prefix.is_empty() {
+        "."
+    } else {
+        prefix
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() || prefix.ends_with('/') {
+        format!("{prefix}{name}")
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
+
+fn unescape(component: &str) -> String {
+    let mut out = String::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_and_classes() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("?at", "cat"));
+        assert!(!matches("?at", "at"));
+        assert!(matches("[ch]at", "hat"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("*", ""));
+        // As in `case`, `*` also crosses `/`.
+        assert!(matches("chat *", "chat host a/b"));
+    }
+
+    #[test]
+    fn escapes_and_literal_characters() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a{b,c}", "a{b,c}"));
+        assert!(!matches("a{b,c}", "ab"));
+        assert!(matches("[{]x", "{x"));
+        // An unclosed class leaves the whole pattern literal.
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn detects_unescaped_wildcards() {
+        assert!(has_wildcards("*.rs"));
+        assert!(has_wildcards("a[bc]"));
+        assert!(!has_wildcards("a\\*b"));
+        assert!(!has_wildcards("plain"));
+    }
+
+    #[test]
+    fn expands_paths_below_a_directory() {
+        let root = std::env::temp_dir().join(format!("heimdal-glob-{}", std::process::id()));
+        fs::create_dir_all(root.join("src/deep")).unwrap();
+        for file in ["a.rs", "b.rs", ".hidden.rs", "src/c.rs", "src/deep/d.rs", "notes.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let in_root = |pattern: &str| glob(pattern, &root);
+        let found = (
+            in_root("*.rs"),
+            in_root(".*.rs"),
+            in_root("src/*.rs"),
+            in_root("**/*.rs"),
+            in_root("*.txt"),
+            glob(&format!("{}/*.md", root.display()), Path::new("")),
+        );
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(found.0, ["a.rs", "b.rs"]);
+        assert_eq!(found.1, [".hidden.rs"]);
+        assert_eq!(found.2, ["src/c.rs"]);
+        assert_eq!(found.3, ["a.rs", "b.rs", "src/c.rs", "src/deep/d.rs"]);
+        assert!(found.4.is_empty());
+        assert_eq!(found.5, [format!("{}/notes.md", root.display())]);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::glob::matches;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+const DEFAULT_SIZE: usize = 1000;
+
+/// Chat commands carry the session password, so they stay out of the history
+/// file unless `HISTIGNORE` says otherwise.
+const DEFAULT_IGNORE: &str = "chat *";
+
+/// Where the shell keeps what it remembers between sessions:
+/// `$XDG_STATE_HOME/heimdal`, or `~/.local/state/heimdal`.
+pub fn state_dir(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let state = var("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state.join("heimdal"))
+}
+
+/// Command history shared by the line editor and the `history` built-in.
+#[derive(Clone, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    max_size: usize,
+    ignore: Vec<String>,
+    changed: bool,
+}
+
+impl History {
+    /// Loads the history file. `HISTFILE` overrides the default
+    /// `$XDG_STATE_HOME/heimdal/history`, `HISTSIZE` caps the number of
+    /// entries and `HISTIGNORE` holds `:`-separated patterns for lines that
+    /// are never recorded.
+    pub fn load(var: impl Fn(&str) -> Option<String>) -> Self {
+        let path = var("HISTFILE")
+            .map(PathBuf::from)
+            .or_else(|| state_dir(&var).map(|dir| dir.join("history")));
+        let max_size = var("HISTSIZE")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_SIZE);
+        let ignore = var("HISTIGNORE")
+            .unwrap_or_else(|| DEFAULT_IGNORE.to_string())
+            .split(':')
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let mut history = History {
+            entries: Vec::new(),
+            path,
+            max_size,
+            ignore,
+            changed: false,
+        };
+        if let Some(content) = history
+            .path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+        {
+            for line in content.lines() {
+                history.push(&decode(line));
+            }
+        }
+        history.changed = false;
+        history
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Records a line typed at the prompt and saves the history file. Lines
+    /// starting with a space are skipped, and so are lines where the whole
+    /// line or any of `commands`, the simple commands it is made of, matches
+    /// `HISTIGNORE`. Returns whether the line was recorded.
+    pub fn add(&mut self, line: &str, commands: &[String]) -> io::Result<bool> {
+        let ignored = |text: &str| self.ignore.iter().any(|pattern| matches(pattern, text));
+        if line.trim().is_empty()
+            || line.starts_with(' ')
+            || ignored(line)
+            || commands.iter().any(|command| ignored(command))
+        {
+            return Ok(false);
+        }
+        self.push(line);
+        self.save()?;
+        Ok(true)
+    }
+
+    // Older copies of a repeated line are dropped so each command appears once.
+    fn push(&mut self, line: &str) {
+        if let Some(index) = self.entries.iter().position(|entry| entry == line) {
+            self.entries.remove(index);
+            self.changed = true;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > self.max_size {
+            let excess = self.entries.len() - self.max_size;
+            self.entries.drain(..excess);
+            self.changed = true;
+        }
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.entries.clear();
+        self.changed = true;
+        self.save()
+    }
+
+    /// Whether entries were removed since the last call, meaning the line
+    /// editor's copy needs to be rebuilt.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Rewrites the history file. The file is readable by its owner only, and
+    /// is written beside the old one and renamed over it so a crash part way
+    /// through never leaves it truncated.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = String::new();
+        for entry in &self.entries {
+            content.push_str(&encode(entry));
+            content.push('\n');
+        }
+
+        let mut temp_name = path.file_name().map(OsString::from).unwrap_or_default();
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp = path.with_file_name(temp_name);
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        written
+    }
+
+    /// Expands `!!`, `!n`, `!-n` and `!prefix` references to earlier
+    /// commands. Returns `None` when the line has no references.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let mut out = String::new();
+        let mut expanded = false;
+        let mut in_single_quotes = false;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => {
+                    in_single_quotes = !in_single_quotes;
+                    out.push(c);
+                }
+                '\\' if !in_single_quotes => {
+                    out.push(c);
+                    out.extend(chars.next());
+                }
+                '!' if !in_single_quotes => {
+                    let mut designator = String::new();
+                    if chars.next_if_eq(&'!').is_some() {
+                        designator.push('!');
+                    } else {
+                        while let Some(ch) = chars.next_if(|ch| {
+                            !ch.is_whitespace() && !matches!(ch, '=' | '(' | ';' | '|' | '&' | '"')
+                        }) {
+                            designator.push(ch);
+                        }
+                    }
+                    if designator.is_empty() {
+                        out.push('!');
+                        continue;
+                    }
+                    let entry = self
+                        .find(&designator)
+                        .ok_or_else(|| format!("!{designator}: event not found"))?;
+                    out.push_str(entry);
+                    expanded = true;
+                }
+                _ => out.push(c),
+            }
+        }
+
+        Ok(expanded.then_some(out))
+    }
+
+    fn find(&self, designator: &str) -> Option<&str> {
+        let entry = if designator == "!" {
+            self.entries.last()
+        } else if let Some(back) = designator.strip_prefix('-') {
+            let back: usize = back.parse().ok()?;
+            self.entries
+                .len()
+                .checked_sub(back)
+                .and_then(|index| self.entries.get(index))
+        } else if let Ok(number) = designator.parse::<usize>() {
+            number
+                .checked_sub(1)
+                .and_then(|index| self.entries.get(index))
+        } else {
+            self.entries
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(designator))
+        };
+        entry.map(String::as_str)
+    }
+}
+
+/// Every entry is saved on one line, so commands typed over several lines
+/// have their line breaks written as `\n` and their backslashes doubled.
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Reverses `encode`. Any other backslash, as in files written before
+/// backslashes were doubled, is kept as it is.
+fn decode(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('\\')) => {
+                chars.next();
+                entry.push('\\');
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                entry.push('\n');
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_through_the_file_format() {
+        for entry in [
+            "echo plain",
+            "echo a \\\nb",
+            "if true\nthen echo \\\\\nfi",
+            "printf 'x\\n'",
+            "echo trailing \\",
+            "echo \\\\n",
+        ] {
+            let encoded = encode(entry);
+            assert!(!encoded.contains('\n'), "{encoded:?}");
+            assert_eq!(decode(&encoded), entry);
+        }
+    }
+
+    #[test]
+    fn saved_history_loads_back() {
+        let dir = std::env::temp_dir().join(format!("heimdal-history-{}", std::process::id()));
+        let file = dir.join("history");
+        let var = |name: &str| (name == "HISTFILE").then(|| file.display().to_string());
+        let entries = ["ls", "echo one \\\ntwo", "for x in a\ndo echo $x \\\\\ndone"];
+
+        let mut history = History::load(var);
+        for entry in entries {
+            history.add(entry, &[]).unwrap();
+        }
+        let reloaded = History::load(var);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.entries(), entries);
+    }
+
+    #[test]
+    fn ignored_commands_are_caught_inside_lists() {
+        let mut history = History { ignore: vec![DEFAULT_IGNORE.to_string()], max_size: 10, ..History::default() };
+        let aliases = Default::default();
+        for line in ["chat host 1234 secret", "cd x; chat host 1234 secret", "true && chat guest 1234 pw", "if true; then chat a 1 pw; fi"] {
+            let list = crate::parser::parse(line, &aliases).unwrap().unwrap();
+            assert!(!history.add(line, &list.simple_commands()).unwrap(), "{line}");
+        }
+        assert!(history.add("echo chat", &["echo chat".to_string()]).unwrap());
+        assert_eq!(history.entries(), ["echo chat"]);
+    }
+}
@@ -0,0 +1,181 @@
+use ::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ::ignore::Match;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Files in a directory whose patterns say which entries below it to skip.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// The ignore rules that apply inside one directory: those from its own
+/// `.gitignore` and `.ignore` files and from every directory above it, back
+/// to the top of the git repository. Deeper files take precedence, and
+/// within a file the last matching pattern wins, as in git.
+#[derive(Clone, Default)]
+pub struct Ignore {
+    // Outermost first.
+    sets: Vec<Arc<Gitignore>>,
+}
+
+impl Ignore {
+    /// Rules for a walk starting at `root`, including those of the
+    /// directories above it when it is inside a git repository, and the
+    /// repository's `.git/info/exclude`. `root` should be canonical, and the
+    /// paths later checked should be built from it.
+    pub fn for_root(root: &Path) -> Ignore {
+        let mut dirs: Vec<&Path> = Vec::new();
+        let mut repository = None;
+        for dir in root.ancestors() {
+            dirs.push(dir);
+            if dir.join(".git").exists() {
+                repository = Some(dir);
+                break;
+            }
+        }
+        let Some(top) = repository else {
+            return Ignore::default().child(root);
+        };
+
+        let mut ignore = Ignore::default();
+        if let Ok(text) = fs::read_to_string(top.join(".git/info/exclude")) {
+            ignore.push(top, &text);
+        }
+        for dir in dirs.into_iter().rev() {
+            ignore = ignore.child(dir);
+        }
+        ignore
+    }
+
+    /// The rules for `dir`, a directory inside the one these rules are for.
+    pub fn child(&self, dir: &Path) -> Ignore {
+        let mut ignore = self.clone();
+        for name in IGNORE_FILES {
+            if let Ok(text) = fs::read_to_string(dir.join(name)) {
+                ignore.push(dir, &text);
+            }
+        }
+        ignore
+    }
+
+    // Lines that are not valid patterns are skipped, as git does.
+    fn push(&mut self, base: &Path, text: &str) {
+        let mut builder = GitignoreBuilder::new(base);
+        for line in text.lines() {
+            let _ = builder.add_line(None, line);
+        }
+        if let Ok(set) = builder.build() {
+            if !set.is_empty() {
+                self.sets.push(Arc::new(set));
+            }
+        }
+    }
+
+    /// Whether `path`, found while walking, should be skipped.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for set in self.sets.iter().rev() {
+            if !path.starts_with(set.path()) {
+                continue;
+            }
+            match set.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignores(text: &str, path: &str, is_dir: bool) -> bool {
+        let mut ignore = Ignore::default();
+        ignore.push(Path::new("/repo"), text);
+        ignore.is_ignored(&Path::new("/repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        assert!(ignores("*.log", "a.log", false));
+        assert!(ignores("*.log", "src/deep/a.log", false));
+        assert!(ignores("target", "crates/x/target", true));
+        assert!(!ignores("*.log", "a.txt", false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_from_their_directory() {
+        assert!(ignores("/build", "build", true));
+        assert!(!ignores("/build", "src/build", true));
+        assert!(ignores("docs/*.html", "docs/index.html", false));
+        assert!(!ignores("docs/*.html", "src/docs/index.html", false));
+    }
+
+    #[test]
+    fn double_star_spans_directories() {
+        assert!(ignores("a/**/b", "a/b", false));
+        assert!(ignores("a/**/b", "a/x/y/b", false));
+        assert!(!ignores("a/**/b", "c/a/x/b", false));
+        assert!(ignores("**/cache", "x/y/cache", true));
+        assert!(ignores("**/cache", "cache", true));
+        assert!(ignores("out/**", "out/a/b", false));
+    }
+
+    #[test]
+    fn negation_and_last_match_wins() {
+        let rules = "*.log\n!keep.log\n";
+        assert!(ignores(rules, "a.log", false));
+        assert!(!ignores(rules, "keep.log", false));
+        assert!(ignores("!keep.log\n*.log\n", "keep.log", false));
+        assert!(ignores("\\!bang", "!bang", false));
+        assert!(ignores("\\#hash", "#hash", false));
+        assert!(!ignores("# comment\n\n", "# comment", false));
+    }
+
+    #[test]
+    fn negated_file_inside_an_ignored_directory_stays_out() {
+        // Git never looks inside an excluded directory, so the walk skips
+        // it before the exception could apply.
+        let rules = "logs/\n!logs/keep.log\n";
+        assert!(ignores(rules, "logs", true));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_directories() {
+        assert!(ignores("logs/", "logs", true));
+        assert!(ignores("logs/", "a/logs", true));
+        assert!(!ignores("logs/", "logs", false));
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let mut ignore = Ignore::default();
+        ignore.push(Path::new("/repo"), "*.gen");
+        ignore.push(Path::new("/repo/sub"), "!*.gen");
+        assert!(ignore.is_ignored(Path::new("/repo/a.gen"), false));
+        assert!(!ignore.is_ignored(Path::new("/repo/sub/a.gen"), false));
+    }
+
+    #[test]
+    fn reads_ignore_files_and_git_info_exclude() {
+        let root = std::env::temp_dir().join(format!("heimdal-ignore-{}", std::process::id()));
+        let sub = root.join("sub");
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(root.join(".git/info/exclude"), "secret\n").unwrap();
+        fs::write(root.join(".gitignore"), "*.tmp\n").unwrap();
+        fs::write(sub.join(".ignore"), "local\n").unwrap();
+
+        let from_sub = Ignore::for_root(&sub);
+        let from_root = Ignore::for_root(&root).child(&sub);
+        fs::remove_dir_all(&root).unwrap();
+
+        for ignore in [from_sub, from_root] {
+            assert!(ignore.is_ignored(&sub.join("secret"), false));
+            assert!(ignore.is_ignored(&sub.join("a.tmp"), false));
+            assert!(ignore.is_ignored(&sub.join("local"), false));
+            assert!(!ignore.is_ignored(&sub.join("a.rs"), false));
+        }
+    }
+}
@@ -0,0 +1,732 @@
+use crate::signals;
+use libc::pid_t;
+use std::io::{self, Write};
+use std::mem;
+use std::os::fd::RawFd;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+
+/// What to signal for every job still around: a process group as a negative
+/// id, or a single process. Kept for passing on SIGTERM and SIGHUP.
+static TARGETS: Mutex<Vec<pid_t>> = Mutex::new(Vec::new());
+
+/// The terminal and the modes it had when job control started, restored if
+/// the shell is killed in the middle of editing a line.
+static SHELL_TERMINAL: OnceLock<(RawFd, libc::termios)> = OnceLock::new();
+
+/// Sends `signal` to every job, waking stopped ones so they see it, and puts
+/// the terminal back the way the shell found it.
+pub fn forward_on_exit(signal: i32) {
+    let targets = TARGETS.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: plain system calls; `modes` was filled in by `tcgetattr`.
+    unsafe {
+        for &target in targets.iter() {
+            libc::kill(target, signal);
+            libc::kill(target, libc::SIGCONT);
+        }
+        if let Some((fd, modes)) = SHELL_TERMINAL.get() {
+            libc::tcsetattr(*fd, libc::TCSANOW, modes);
+        }
+    }
+}
+
+/// One stage of a started pipeline.
+pub enum Stage {
+    /// An external command, by process id.
+    Process(pid_t),
+    /// A built-in, function or control structure running on its own task.
+    Task(JoinHandle<i32>),
+    Done(i32),
+}
+
+/// The process group an external command starts in under job control, and
+/// whether it takes over the terminal.
+#[derive(Clone, Copy)]
+pub struct Group {
+    /// The job's process group, or 0 to start a new one.
+    pgid: pid_t,
+    foreground: bool,
+    terminal: RawFd,
+}
+
+impl Group {
+    /// Puts a just spawned process in the group from the shell's side too,
+    /// so the group exists before the next stage needs it, and hands a
+    /// foreground job the terminal.
+    pub fn adopt(self, pid: pid_t) {
+        let pgid = if self.pgid == 0 { pid } else { self.pgid };
+        // SAFETY: plain system calls; failing because the child already did
+        // them or has exited is harmless.
+        unsafe {
+            libc::setpgid(pid, pgid);
+            if self.foreground {
+                libc::tcsetpgrp(self.terminal, pgid);
+            }
+        }
+    }
+
+    /// Takes the terminal back from a process that failed to start, which
+    /// may have claimed it for a group that no longer exists. It goes to the
+    /// job's earlier stages if there are any, or else to the shell.
+    pub fn abandon(self) {
+        if !self.foreground {
+            return;
+        }
+        // SAFETY: plain system calls on the terminal fd and this process.
+        unsafe {
+            let pgid = if self.pgid == 0 {
+                libc::getpgrp()
+            } else {
+                self.pgid
+            };
+            libc::tcsetpgrp(self.terminal, pgid);
+        }
+    }
+}
+
+/// Runs in a forked child right before `exec`: joins the job's process group
+/// and restores the signals an interactive shell ignores. Only
+/// async-signal-safe calls are allowed here.
+///
+/// A foreground job also takes the terminal here, as well as in
+/// [`Group::adopt`], so a command that reads at once does not get `SIGTTIN`
+/// before the shell gets round to it. If `exec` then fails, the shell takes
+/// the terminal back with [`Group::abandon`].
+pub fn enter_job(group: Option<Group>) {
+    // SAFETY: `setpgid`, `tcsetpgrp` and `signal` are async-signal-safe.
+    unsafe {
+        if let Some(group) = group {
+            let pgid = if group.pgid == 0 {
+                libc::getpid()
+            } else {
+                group.pgid
+            };
+            libc::setpgid(0, pgid);
+            if group.foreground {
+                libc::tcsetpgrp(group.terminal, pgid);
+            }
+        }
+        for signal in [
+            libc::SIGINT,
+            libc::SIGQUIT,
+            libc::SIGTSTP,
+            libc::SIGTTIN,
+            libc::SIGTTOU,
+        ] {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+}
+
+/// The terminal an interactive shell controls, with its own process group
+/// and the terminal modes to restore whenever it gets the terminal back.
+struct Terminal {
+    fd: RawFd,
+    pgid: pid_t,
+    modes: libc::termios,
+}
+
+pub struct Job {
+    id: usize,
+    command: String,
+    pgid: Option<pid_t>,
+    stages: Vec<Stage>,
+    stopped: bool,
+    /// Whether the user has seen the job's current state, so the prompt does
+    /// not announce it again.
+    reported: bool,
+    /// Terminal modes the job had when it stopped, given back by `fg`.
+    modes: Option<libc::termios>,
+    /// When the job was last started, stopped or resumed. The most recent one
+    /// is the current job `%+`, the one before it `%-`.
+    touched: u64,
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let mut targets = TARGETS.lock().unwrap_or_else(|e| e.into_inner());
+        for target in self.targets() {
+            if let Some(index) = targets.iter().position(|&other| other == target) {
+                targets.swap_remove(index);
+            }
+        }
+    }
+}
+
+/// How waiting for a job ended.
+enum Outcome {
+    Exited(i32),
+    Stopped(i32),
+}
+
+/// A state change `waitpid` reported for one process.
+enum Change {
+    Exited(i32),
+    Killed(i32),
+    Stopped(i32),
+    Continued,
+    None,
+}
+
+impl Job {
+    /// A job made of the stages of a pipeline. `pgid` is the process group of
+    /// its external commands when the shell does job control.
+    pub fn new(command: String, stages: Vec<Stage>, pgid: Option<pid_t>) -> Job {
+        let job = Job {
+            id: 0,
+            command,
+            pgid,
+            stages,
+            stopped: false,
+            reported: false,
+            modes: None,
+            touched: 0,
+        };
+        TARGETS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(job.targets());
+        job
+    }
+
+    fn targets(&self) -> Vec<pid_t> {
+        match self.pgid {
+            Some(pgid) => vec![-pgid],
+            None => self.pids().collect(),
+        }
+    }
+
+    /// The process id of the last external command, for `$!`.
+    pub fn last_pid(&self) -> Option<pid_t> {
+        self.pids().last()
+    }
+
+    fn pids(&self) -> impl Iterator<Item = pid_t> + '_ {
+        self.stages.iter().filter_map(|stage| match stage {
+            Stage::Process(pid) => Some(*pid),
+            _ => None,
+        })
+    }
+
+    fn is_done(&self) -> bool {
+        self.stages.iter().all(|stage| matches!(stage, Stage::Done(_)))
+    }
+
+    /// The status of the last stage, like `$?` for a pipeline.
+    fn status(&self) -> i32 {
+        match self.stages.last() {
+            Some(Stage::Done(status)) => *status,
+            _ => 0,
+        }
+    }
+
+    fn signal(&self, signal: i32) {
+        for target in self.targets() {
+            // SAFETY: plain system call on processes this shell started.
+            unsafe {
+                libc::kill(target, signal);
+            }
+        }
+    }
+
+    /// Waits until every stage has finished or one of its processes stops.
+    /// Processes come first, since a built-in stage may be blocked writing to
+    /// a stopped process.
+    async fn wait(&mut self) -> Outcome {
+        for index in 0..self.stages.len() {
+            let Stage::Process(pid) = self.stages[index] else {
+                continue;
+            };
+            let change = task::spawn_blocking(move || wait_pid(pid, libc::WUNTRACED))
+                .await
+                .unwrap_or(Change::Exited(1));
+            match change {
+                Change::Exited(status) => self.stages[index] = Stage::Done(status),
+                Change::Killed(signal) => {
+                    // Like other shells, give up on the rest of the command
+                    // line when Ctrl-C killed the job.
+                    if signal == libc::SIGINT {
+                        signals::interrupt();
+                    }
+                    self.stages[index] = Stage::Done(128 + signal);
+                }
+                Change::Stopped(signal) => {
+                    self.stopped = true;
+                    return Outcome::Stopped(signal);
+                }
+                Change::Continued | Change::None => {}
+            }
+        }
+        for stage in &mut self.stages {
+            if let Stage::Task(handle) = stage {
+                *stage = Stage::Done(handle.await.unwrap_or(1));
+            }
+        }
+        self.stopped = false;
+        Outcome::Exited(self.status())
+    }
+
+    /// Collects whatever happened to the job's stages without blocking.
+    async fn poll(&mut self) {
+        let before = self.state();
+        for stage in &mut self.stages {
+            match stage {
+                Stage::Process(pid) => {
+                    match wait_pid(*pid, libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED) {
+                        Change::Exited(status) => *stage = Stage::Done(status),
+                        Change::Killed(signal) => *stage = Stage::Done(128 + signal),
+                        Change::Stopped(_) => self.stopped = true,
+                        Change::Continued => self.stopped = false,
+                        Change::None => {}
+                    }
+                }
+                Stage::Task(handle) if handle.is_finished() => {
+                    *stage = Stage::Done(handle.await.unwrap_or(1));
+                }
+                _ => {}
+            }
+        }
+        if self.is_done() {
+            self.stopped = false;
+        }
+        if self.state() != before {
+            self.reported = false;
+        }
+    }
+
+    fn state(&self) -> String {
+        match self.status() {
+            _ if self.stopped => "Stopped".to_string(),
+            _ if !self.is_done() => "Running".to_string(),
+            0 => "Done".to_string(),
+            status => format!("Exit {status}"),
+        }
+    }
+}
+
+fn wait_pid(pid: pid_t, flags: i32) -> Change {
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` is a valid pointer for the duration of the call.
+        let result = unsafe { libc::waitpid(pid, &mut status, flags) };
+        if result == 0 {
+            return Change::None;
+        }
+        if result == -1 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // Nothing left to wait for, so treat the process as gone.
+            return Change::Exited(0);
+        }
+        return if libc::WIFEXITED(status) {
+            Change::Exited(libc::WEXITSTATUS(status))
+        } else if libc::WIFSIGNALED(status) {
+            Change::Killed(libc::WTERMSIG(status))
+        } else if libc::WIFSTOPPED(status) {
+            Change::Stopped(libc::WSTOPSIG(status))
+        } else {
+            Change::Continued
+        };
+    }
+}
+
+/// Background and stopped jobs, and the terminal when the shell does job
+/// control. A copy of the shell, running as a subshell, starts with no jobs
+/// and leaves the terminal alone.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    terminal: Option<Terminal>,
+    /// Processes of disowned jobs, reaped without a word.
+    disowned: Vec<pid_t>,
+    touches: u64,
+}
+
+impl Clone for Jobs {
+    fn clone(&self) -> Self {
+        Jobs::default()
+    }
+}
+
+impl Jobs {
+    /// Takes control of the terminal on stdin: waits until the shell is in
+    /// the foreground, moves it to its own process group and ignores the
+    /// signals meant to stop jobs, as interactive shells do.
+    pub fn enable_job_control(&mut self) -> io::Result<()> {
+        let fd = libc::STDIN_FILENO;
+        // SAFETY: plain system calls on stdin and this process, with `modes`
+        // filled in by `tcgetattr` before use.
+        unsafe {
+            loop {
+                let foreground = libc::tcgetpgrp(fd);
+                if foreground == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if foreground == libc::getpgrp() {
+                    break;
+                }
+                libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+            }
+            for signal in [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+
+            let pid = libc::getpid();
+            if libc::getpgrp() != pid && libc::setpgid(0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::tcsetpgrp(fd, pid) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut modes = mem::zeroed();
+            if libc::tcgetattr(fd, &mut modes) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let _ = SHELL_TERMINAL.set((fd, modes));
+            self.terminal = Some(Terminal {
+                fd,
+                pgid: pid,
+                modes,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn job_control(&self) -> bool {
+        self.terminal.is_some()
+    }
+
+    /// The group for the next external command of a job whose group is
+    /// `pgid` so far, or `None` without job control.
+    pub fn group(&self, pgid: Option<pid_t>, foreground: bool) -> Option<Group> {
+        self.terminal.as_ref().map(|terminal| Group {
+            pgid: pgid.unwrap_or(0),
+            foreground,
+            terminal: terminal.fd,
+        })
+    }
+
+    fn touch(&mut self) -> u64 {
+        self.touches += 1;
+        self.touches
+    }
+
+    /// Adds a job to the table, numbering it if it is new.
+    fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.last().map_or(1, |last| last.id + 1);
+        }
+        let id = job.id;
+        let index = self.jobs.partition_point(|other| other.id < id);
+        self.jobs.insert(index, job);
+        id
+    }
+
+    /// Records a job started with `&` and returns its number.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        job.touched = self.touch();
+        self.insert(job)
+    }
+
+    /// Waits for a job in the foreground, handing it the terminal under job
+    /// control. A job that stops goes into the table and its status is
+    /// 128 plus the stop signal, as with a signal exit.
+    pub async fn run_in_foreground(&mut self, mut job: Job) -> i32 {
+        if let Some((terminal, pgid)) = self.terminal.as_ref().zip(job.pgid) {
+            // SAFETY: plain system calls on the terminal fd.
+            unsafe {
+                if let Some(modes) = &job.modes {
+                    libc::tcsetattr(terminal.fd, libc::TCSADRAIN, modes);
+                }
+                libc::tcsetpgrp(terminal.fd, pgid);
+            }
+        }
+        if job.stopped {
+            job.signal(libc::SIGCONT);
+            job.stopped = false;
+        }
+
+        let outcome = job.wait().await;
+        // Taken back even from a job with no group of its own, in case one
+        // of its commands grabbed the terminal and then failed to start.
+        if let Some(terminal) = &self.terminal {
+            // SAFETY: as above; `modes` is filled in by `tcgetattr`.
+            unsafe {
+                libc::tcsetpgrp(terminal.fd, terminal.pgid);
+                if job.stopped {
+                    let mut modes = mem::zeroed();
+                    if libc::tcgetattr(terminal.fd, &mut modes) == 0 {
+                        job.modes = Some(modes);
+                    }
+                }
+                libc::tcsetattr(terminal.fd, libc::TCSADRAIN, &terminal.modes);
+            }
+        }
+
+        match outcome {
+            Outcome::Exited(status) => status,
+            Outcome::Stopped(signal) => {
+                job.touched = self.touch();
+                job.reported = true;
+                let command = job.command.clone();
+                let id = self.insert(job);
+                eprintln!("\n[{id}]+  {:<24}{command}", "Stopped");
+                128 + signal
+            }
+        }
+    }
+
+    /// `fg`: resumes a job in the foreground and waits for it.
+    pub async fn foreground(&mut self, index: usize) -> i32 {
+        let job = self.jobs.remove(index);
+        self.run_in_foreground(job).await
+    }
+
+    pub fn command(&self, index: usize) -> &str {
+        &self.jobs[index].command
+    }
+
+    /// Job numbers, lowest first.
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    /// `bg`: lets a stopped job carry on in the background.
+    pub fn background(&mut self, index: usize, err: &mut dyn Write) -> io::Result<()> {
+        let touched = self.touch();
+        let job = &mut self.jobs[index];
+        if !job.stopped {
+            return writeln!(err, "bg: job {} already in background", job.id);
+        }
+        job.signal(libc::SIGCONT);
+        job.stopped = false;
+        job.reported = true;
+        job.touched = touched;
+        writeln!(err, "[{}]+ {} &", job.id, job.command)
+    }
+
+    /// `wait`: waits for a job to finish, or stop, and returns its status.
+    /// The job keeps running in the background, so this polls and Ctrl-C can
+    /// end the wait with status 130.
+    pub async fn wait_for(&mut self, index: usize) -> i32 {
+        let job = &mut self.jobs[index];
+        loop {
+            job.poll().await;
+            if job.stopped {
+                return 128 + libc::SIGTSTP;
+            }
+            if job.is_done() {
+                break;
+            }
+            if signals::interrupted() {
+                return 130;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = job.status();
+        self.jobs.remove(index);
+        status
+    }
+
+    /// `disown`: forgets a job, which then runs on unattended.
+    pub fn disown(&mut self, index: usize) {
+        let job = self.jobs.remove(index);
+        self.disowned.extend(job.pids());
+    }
+
+    pub fn has_stopped(&self) -> bool {
+        self.jobs.iter().any(|job| job.stopped)
+    }
+
+    /// Collects state changes of every job without blocking.
+    pub async fn update(&mut self) {
+        for job in &mut self.jobs {
+            job.poll().await;
+        }
+        self.disowned.retain(|&pid| {
+            matches!(
+                wait_pid(pid, libc::WNOHANG),
+                Change::None | Change::Stopped(_) | Change::Continued
+            )
+        });
+    }
+
+    /// Before a prompt, reports jobs that finished or stopped since the last
+    /// one and drops the finished ones.
+    pub async fn notify(&mut self, err: &mut dyn Write) -> io::Result<()> {
+        self.update().await;
+        for index in 0..self.jobs.len() {
+            if !self.jobs[index].reported && (self.jobs[index].stopped || self.jobs[index].is_done())
+            {
+                writeln!(err, "{}", self.describe(index, false))?;
+                self.jobs[index].reported = true;
+            }
+        }
+        self.jobs.retain(|job| !job.is_done());
+        Ok(())
+    }
+
+    /// `jobs`: lists every job, with process ids when `long` is set, and
+    /// drops the finished ones once shown.
+    pub async fn list(&mut self, long: bool, out: &mut dyn Write) -> io::Result<()> {
+        self.update().await;
+        for index in 0..self.jobs.len() {
+            writeln!(out, "{}", self.describe(index, long))?;
+            self.jobs[index].reported = true;
+        }
+        self.jobs.retain(|job| !job.is_done());
+        Ok(())
+    }
+
+    /// `jobs -p`: the process group of each job, or its first process.
+    pub async fn list_pids(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.update().await;
+        for job in &self.jobs {
+            if let Some(pid) = job.pgid.or_else(|| job.pids().next()) {
+                writeln!(out, "{pid}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A line like `[1]+  Running                 sleep 10 &`.
+    fn describe(&self, index: usize, long: bool) -> String {
+        let job = &self.jobs[index];
+        let marker = match self.recent().iter().position(|&other| other == index) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        };
+        let pids = if long {
+            let pids: Vec<String> = job.pids().map(|pid| pid.to_string()).collect();
+            format!(" {}", pids.join(" "))
+        } else {
+            String::new()
+        };
+        let background = if job.stopped || job.is_done() { "" } else { " &" };
+        format!(
+            "[{}]{marker}{pids}  {:<24}{}{background}",
+            job.id,
+            job.state(),
+            job.command
+        )
+    }
+
+    /// Job indices from the most recently used.
+    fn recent(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.jobs.len()).collect();
+        indices.sort_by_key(|&index| std::cmp::Reverse(self.jobs[index].touched));
+        indices
+    }
+
+    /// Resolves a job spec: `%n`, `%+` or `%%` for the current job, `%-` for
+    /// the previous one, `%name` for a job whose command starts with `name`
+    /// and `%?text` for one containing `text`. No spec means the current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let recent = self.recent();
+        let spec = spec.unwrap_or("%+");
+        let found = match spec.strip_prefix('%').unwrap_or(spec) {
+            "" | "%" | "+" => recent.first().copied(),
+            "-" => recent.get(1).copied(),
+            text => {
+                if let Ok(id) = text.parse::<usize>() {
+                    self.jobs.iter().position(|job| job.id == id)
+                } else if let Some(text) = text.strip_prefix('?') {
+                    self.jobs.iter().position(|job| job.command.contains(text))
+                } else {
+                    self.jobs.iter().position(|job| job.command.starts_with(text))
+                }
+            }
+        };
+        found.ok_or_else(|| match spec {
+            "%+" | "%%" | "%" => "current: no such job".to_string(),
+            _ => format!("{spec}: no such job"),
+        })
+    }
+
+    /// The job running process `pid`, for `wait pid`.
+    pub fn find_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs.iter().position(|job| job.pids().any(|other| other == pid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(command: &str, status: i32) -> Job {
+        Job::new(command.to_string(), vec![Stage::Done(status)], None)
+    }
+
+    fn running(command: &str) -> Job {
+        let stage = Stage::Task(tokio::spawn(std::future::pending()));
+        Job::new(command.to_string(), vec![stage], None)
+    }
+
+    async fn listing(jobs: &mut Jobs) -> String {
+        let mut out = Vec::new();
+        jobs.list(false, &mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn jobs_are_numbered_after_the_highest() {
+        let mut jobs = Jobs::default();
+        assert_eq!(jobs.add(running("a")), 1);
+        assert_eq!(jobs.add(finished("b", 0)), 2);
+        assert_eq!(jobs.add(running("c")), 3);
+        assert_eq!(jobs.wait_for(1).await, 0);
+        assert_eq!(jobs.ids(), [1, 3]);
+        assert_eq!(jobs.add(running("d")), 4);
+
+        jobs.disown(2);
+        jobs.disown(1);
+        assert_eq!(jobs.add(running("e")), 2);
+    }
+
+    #[tokio::test]
+    async fn job_specs() {
+        let mut jobs = Jobs::default();
+        for command in ["sleep 10", "make all", "vim notes"] {
+            jobs.add(running(command));
+        }
+        assert_eq!(jobs.find(None), Ok(2));
+        assert_eq!(jobs.find(Some("%%")), Ok(2));
+        assert_eq!(jobs.find(Some("%-")), Ok(1));
+        assert_eq!(jobs.find(Some("%1")), Ok(0));
+        assert_eq!(jobs.find(Some("%make")), Ok(1));
+        assert_eq!(jobs.find(Some("%?notes")), Ok(2));
+        assert_eq!(jobs.find(Some("%9")), Err("%9: no such job".to_string()));
+        assert_eq!(Jobs::default().find(None), Err("current: no such job".to_string()));
+    }
+
+    #[tokio::test]
+    async fn listing_shows_state_and_drops_finished_jobs() {
+        let mut jobs = Jobs::default();
+        jobs.add(running("sleep 10"));
+        jobs.add(finished("false", 1));
+        jobs.add(finished("true", 0));
+        assert_eq!(
+            listing(&mut jobs).await,
+            format!(
+                "[1]   {:<24}sleep 10 &\n[2]-  {:<24}false\n[3]+  {:<24}true\n",
+                "Running", "Exit 1", "Done"
+            )
+        );
+        assert_eq!(listing(&mut jobs).await, format!("[1]+  {:<24}sleep 10 &\n", "Running"));
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_are_announced_once() {
+        let mut jobs = Jobs::default();
+        jobs.add(finished("true", 0));
+        jobs.add(running("sleep 10"));
+        let mut err = Vec::new();
+        jobs.notify(&mut err).await.unwrap();
+        jobs.notify(&mut err).await.unwrap();
+        assert_eq!(String::from_utf8(err).unwrap(), format!("[1]-  {:<24}true\n", "Done"));
+        assert_eq!(jobs.ids(), [2]);
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    Pipe,
+    And,
+    Or,
+    Semi,
+    /// `&`, running the preceding and-or list in the background.
+    Amp,
+    /// `;;`, ending a `case` arm.
+    DoubleSemi,
+    LParen,
+    RParen,
+    Newline,
+    Redirect(Option<u32>, RedirectOp),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `>&`, duplicating another descriptor.
+    Duplicate,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Pipe => write!(f, "|"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Semi => write!(f, ";"),
+            Token::Amp => write!(f, "&"),
+            Token::DoubleSemi => write!(f, ";;"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Newline => write!(f, "newline"),
+            Token::Redirect(fd, op) => {
+                if let Some(fd) = fd {
+                    write!(f, "{fd}")?;
+                }
+                match op {
+                    RedirectOp::Read => write!(f, "<"),
+                    RedirectOp::Write => write!(f, ">"),
+                    RedirectOp::Append => write!(f, ">>"),
+                    RedirectOp::Duplicate => write!(f, ">&"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    UnterminatedQuote(char),
+    UnterminatedBrace,
+    UnterminatedParen,
+    TrailingBackslash,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedQuote(q) => write!(f, "unterminated {q} quote"),
+            LexError::UnterminatedBrace => write!(f, "unterminated ${{"),
+            LexError::UnterminatedParen => write!(f, "unterminated ("),
+            LexError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+/// Splits a line into words and operators. Quotes and escapes are kept in each
+/// word so that later stages can still tell quoted text apart; expansion strips
+/// them once the command runs.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => end_word(&mut tokens, &mut current),
+            '\n' => {
+                end_word(&mut tokens, &mut current);
+                tokens.push(Token::Newline);
+            }
+            // A `#` starting a word comments out the rest of the line, which
+            // also covers a script's `#!` line.
+            '#' if current.is_empty() => {
+                while chars.next_if(|ch| *ch != '\n').is_some() {}
+            }
+            '|' => {
+                end_word(&mut tokens, &mut current);
+                if chars.next_if_eq(&'|').is_some() {
+                    tokens.push(Token::Or);
+                } else {
+                    tokens.push(Token::Pipe);
+                }
+            }
+            '&' => {
+                end_word(&mut tokens, &mut current);
+                if chars.next_if_eq(&'&').is_some() {
+                    tokens.push(Token::And);
+                } else {
+                    tokens.push(Token::Amp);
+                }
+            }
+            ';' => {
+                end_word(&mut tokens, &mut current);
+                if chars.next_if_eq(&';').is_some() {
+                    tokens.push(Token::DoubleSemi);
+                } else {
+                    tokens.push(Token::Semi);
+                }
+            }
+            '(' | ')' => {
+                end_word(&mut tokens, &mut current);
+                tokens.push(if c == '(' { Token::LParen } else { Token::RParen });
+            }
+            // `<(cmd)` and `>(cmd)` are process substitutions, part of a word.
+            '<' | '>' if chars.peek() == Some(&'(') => {
+                current.push(c);
+                read_parenthesized(&mut chars, &mut current)?;
+            }
+            '<' | '>' => {
+                // A word made only of digits right before the operator is the
+                // descriptor being redirected, as in `2>errors.log`.
+                let fd = if !current.is_empty() && current.chars().all(|ch| ch.is_ascii_digit()) {
+                    current.parse().ok()
+                } else {
+                    None
+                };
+                if fd.is_some() {
+                    current.clear();
+                } else {
+                    end_word(&mut tokens, &mut current);
+                }
+
+                let op = match (c, chars.peek()) {
+                    ('<', _) => RedirectOp::Read,
+                    (_, Some('>')) => {
+                        chars.next();
+                        RedirectOp::Append
+                    }
+                    (_, Some('&')) => {
+                        chars.next();
+                        RedirectOp::Duplicate
+                    }
+                    _ => RedirectOp::Write,
+                };
+                tokens.push(Token::Redirect(fd, op));
+            }
+            '\\' => {
+                let next = chars.next().ok_or(LexError::TrailingBackslash)?;
+                if next == '\n' {
+                    continue;
+                }
+                current.push('\\');
+                current.push(next);
+            }
+            '\'' => read_single_quoted(&mut chars, &mut current)?,
+            '"' => read_double_quoted(&mut chars, &mut current)?,
+            '`' => read_backquoted(&mut chars, &mut current)?,
+            '$' if chars.peek() == Some(&'{') => read_braced(&mut chars, &mut current)?,
+            '$' if chars.peek() == Some(&'(') => {
+                current.push('$');
+                read_parenthesized(&mut chars, &mut current)?;
+            }
+            _ => current.push(c),
+        }
+    }
+
+    end_word(&mut tokens, &mut current);
+    Ok(tokens)
+}
+
+// Raw words keep their quotes, so even `""` leaves something in `current`.
+fn end_word(tokens: &mut Vec<Token>, current: &mut String) {
+    if !current.is_empty() {
+        tokens.push(Token::Word(std::mem::take(current)));
+    }
+}
+
+fn read_single_quoted(chars: &mut Peekable<Chars>, current: &mut String) -> Result<(), LexError> {
+    current.push('\'');
+    loop {
+        match chars.next() {
+            Some('\'') => break,
+            Some(ch) => current.push(ch),
+            None => return Err(LexError::UnterminatedQuote('\'')),
+        }
+    }
+    current.push('\'');
+    Ok(())
+}
+
+fn read_double_quoted(chars: &mut Peekable<Chars>, current: &mut String) -> Result<(), LexError> {
+    current.push('"');
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => {
+                let next = chars.next().ok_or(LexError::UnterminatedQuote('"'))?;
+                current.push('\\');
+                current.push(next);
+            }
+            Some('`') => read_backquoted(chars, current)?,
+            Some('$') if chars.peek() == Some(&'(') => {
+                current.push('$');
+                read_parenthesized(chars, current)?;
+            }
+            Some(ch) => current.push(ch),
+            None => return Err(LexError::UnterminatedQuote('"')),
+        }
+    }
+    current.push('"');
+    Ok(())
+}
+
+// Called on the `$` of `${`; spaces inside the braces, as in `${NAME:-a b}`,
+// belong to the word.
+fn read_braced(chars: &mut Peekable<Chars>, current: &mut String) -> Result<(), LexError> {
+    current.push('$');
+    let mut depth = 0;
+    loop {
+        match chars.next() {
+            Some('{') => {
+                depth += 1;
+                current.push('{');
+            }
+            Some('}') => {
+                depth -= 1;
+                current.push('}');
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            Some('\'') => read_single_quoted(chars, current)?,
+            Some('"') => read_double_quoted(chars, current)?,
+            Some('\\') => {
+                current.push('\\');
+                current.push(chars.next().ok_or(LexError::UnterminatedBrace)?);
+            }
+            Some(ch) => current.push(ch),
+            None => return Err(LexError::UnterminatedBrace),
+        }
+    }
+}
+
+// Called on the `(` of `$(`, `<(` or `>(`. The command inside is kept as is,
+// up to the matching `)`.
+fn read_parenthesized(chars: &mut Peekable<Chars>, current: &mut String) -> Result<(), LexError> {
+    let mut depth = 0;
+    loop {
+        match chars.next() {
+            Some('(') => {
+                depth += 1;
+                current.push('(');
+            }
+            Some(')') => {
+                depth -= 1;
+                current.push(')');
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            Some('\'') => read_single_quoted(chars, current)?,
+            Some('"') => read_double_quoted(chars, current)?,
+            Some('`') => read_backquoted(chars, current)?,
+            Some('\\') => {
+                current.push('\\');
+                current.push(chars.next().ok_or(LexError::UnterminatedParen)?);
+            }
+            Some(ch) => current.push(ch),
+            None => return Err(LexError::UnterminatedParen),
+        }
+    }
+}
+
+fn read_backquoted(chars: &mut Peekable<Chars>, current: &mut String) -> Result<(), LexError> {
+    current.push('`');
+    loop {
+        match chars.next() {
+            Some('`') => break,
+            Some('\\') => {
+                current.push('\\');
+                current.push(chars.next().ok_or(LexError::UnterminatedQuote('`'))?);
+            }
+            Some(ch) => current.push(ch),
+            None => return Err(LexError::UnterminatedQuote('`')),
+        }
+    }
+    current.push('`');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    #[test]
+    fn quotes_and_escapes_stay_in_their_word() {
+        assert_eq!(
+            tokenize(r#"echo 'a b' "c d"e f\ g"#).unwrap(),
+            [word("echo"), word("'a b'"), word("\"c d\"e"), word("f\\ g")]
+        );
+        assert_eq!(tokenize("echo ''").unwrap(), [word("echo"), word("''")]);
+        assert_eq!(tokenize(r#""a \" b""#).unwrap(), [word(r#""a \" b""#)]);
+        assert_eq!(tokenize("one \\\ntwo").unwrap(), [word("one"), word("two")]);
+    }
+
+    #[test]
+    fn operators_split_words() {
+        assert_eq!(
+            tokenize("a|b||c&&d;e&").unwrap(),
+            [
+                word("a"),
+                Token::Pipe,
+                word("b"),
+                Token::Or,
+                word("c"),
+                Token::And,
+                word("d"),
+                Token::Semi,
+                word("e"),
+                Token::Amp,
+            ]
+        );
+        assert_eq!(
+            tokenize("(a)\nb;;").unwrap(),
+            [Token::LParen, word("a"), Token::RParen, Token::Newline, word("b"), Token::DoubleSemi]
+        );
+    }
+
+    #[test]
+    fn redirections_take_a_leading_descriptor() {
+        assert_eq!(
+            tokenize("cmd >out 2>>log <in 2>&1 a2>b").unwrap(),
+            [
+                word("cmd"),
+                Token::Redirect(None, RedirectOp::Write),
+                word("out"),
+                Token::Redirect(Some(2), RedirectOp::Append),
+                word("log"),
+                Token::Redirect(None, RedirectOp::Read),
+                word("in"),
+                Token::Redirect(Some(2), RedirectOp::Duplicate),
+                word("1"),
+                word("a2"),
+                Token::Redirect(None, RedirectOp::Write),
+                word("b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn substitutions_and_braces_are_one_word() {
+        assert_eq!(
+            tokenize("echo $(ls | wc -l) `date` ${X:-a b} <(sort f)").unwrap(),
+            [word("echo"), word("$(ls | wc -l)"), word("`date`"), word("${X:-a b}"), word("<(sort f)")]
+        );
+        assert_eq!(tokenize("echo \"$(echo \")\")\"").unwrap(), [word("echo"), word("\"$(echo \")\")\"")]);
+    }
+
+    #[test]
+    fn comments_start_only_at_a_word() {
+        assert_eq!(tokenize("echo a#b # rest\nls").unwrap(), [word("echo"), word("a#b"), Token::Newline, word("ls")]);
+    }
+
+    #[test]
+    fn unterminated_input() {
+        assert_eq!(tokenize("echo 'a"), Err(LexError::UnterminatedQuote('\'')));
+        assert_eq!(tokenize("echo \"a"), Err(LexError::UnterminatedQuote('"')));
+        assert_eq!(tokenize("echo `a"), Err(LexError::UnterminatedQuote('`')));
+        assert_eq!(tokenize("echo ${A"), Err(LexError::UnterminatedBrace));
+        assert_eq!(tokenize("echo $(a"), Err(LexError::UnterminatedParen));
+        assert_eq!(tokenize("echo \\"), Err(LexError::TrailingBackslash));
+    }
+}
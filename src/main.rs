@@ -1,9 +1,30 @@
 mod commands;
 mod chat;
 mod shell;
+mod signals;
 mod commands_enum;
+mod lexer;
+mod parser;
+mod executor;
+mod expand;
+mod glob;
+mod history;
+mod ignore;
+mod jobs;
+mod prompt;
+mod replace;
+mod search;
+mod tree;
+mod editor;
+mod streams;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
 
 #[tokio::main]
 async fn main() {
-    shell::run().await;
+    let status = shell::start(env::args().skip(1).collect()).await;
+    let _ = io::stdout().flush();
+    process::exit(status);
 }
\ No newline at end of file
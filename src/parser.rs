@@ -0,0 +1,879 @@
+use crate::lexer::{tokenize, LexError, RedirectOp, Token};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::vec::IntoIter;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Lex(LexError),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Lex(e) => write!(f, "{e}"),
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected token `{token}`"),
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError::Lex(e)
+    }
+}
+
+/// Words and redirect targets are kept raw, quotes included, and only expanded
+/// when the command runs so that `$?` sees the status of the previous command.
+#[derive(Clone)]
+pub struct Redirect {
+    pub fd: u32,
+    pub op: RedirectOp,
+    pub target: String,
+}
+
+#[derive(Clone, Default)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command name, with raw values.
+    pub assignments: Vec<(String, String)>,
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl SimpleCommand {
+    fn is_empty(&self) -> bool {
+        self.assignments.is_empty() && self.words.is_empty() && self.redirects.is_empty()
+    }
+}
+
+#[derive(Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// A control structure or block, with redirects applying to all of it.
+    Compound(Compound, Vec<Redirect>),
+    /// `fn name { list }`, defining a function.
+    Function(String, Arc<List>),
+}
+
+#[derive(Clone)]
+pub enum Compound {
+    /// `{ list; }`, run in the current shell.
+    Group(List),
+    /// `( list )`, run in a copy of the shell.
+    Subshell(List),
+    /// `if` and each `elif` as condition and body, then the `else` body.
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while` loop, or `until` loop when `until` is set.
+    While {
+        condition: List,
+        body: List,
+        until: bool,
+    },
+    /// `for name in words; do ...; done`. Without `in`, loops over the
+    /// positional parameters.
+    For {
+        name: String,
+        words: Option<Vec<String>>,
+        body: List,
+    },
+    Case {
+        word: String,
+        arms: Vec<CaseArm>,
+    },
+}
+
+/// `pattern | pattern) list ;;` inside a `case`, with raw patterns.
+#[derive(Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<String>,
+    pub body: List,
+}
+
+#[derive(Clone)]
+pub struct Pipeline {
+    /// `!` in front of the pipeline inverts its status.
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// `&&`, run only if the previous pipeline succeeded.
+    And,
+    /// `||`, run only if the previous pipeline failed.
+    Or,
+}
+
+/// Pipelines chained with `&&` and `||`.
+#[derive(Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Ended with `&`, so the shell does not wait for it.
+    pub background: bool,
+}
+
+/// Sequence of and-or lists separated by `;`, `&` or newlines.
+#[derive(Clone)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+impl List {
+    /// The words of every simple command in the list, however deeply it is
+    /// nested, each joined by spaces.
+    pub fn simple_commands(&self) -> Vec<String> {
+        let mut found = Vec::new();
+        self.collect_simple_commands(&mut found);
+        found
+    }
+
+    fn collect_simple_commands(&self, found: &mut Vec<String>) {
+        let pipelines = self
+            .items
+            .iter()
+            .flat_map(|and_or| std::iter::once(&and_or.first).chain(and_or.rest.iter().map(|(_, pipeline)| pipeline)));
+        for command in pipelines.flat_map(|pipeline| &pipeline.commands) {
+            match command {
+                Command::Simple(simple) if !simple.words.is_empty() => found.push(simple.words.join(" ")),
+                Command::Simple(_) => {}
+                Command::Function(_, body) => body.collect_simple_commands(found),
+                Command::Compound(compound, _) => {
+                    for list in compound.lists() {
+                        list.collect_simple_commands(found);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Compound {
+    /// The lists inside, conditions included.
+    fn lists(&self) -> Vec<&List> {
+        match self {
+            Compound::Group(list) | Compound::Subshell(list) => vec![list],
+            Compound::If { branches, otherwise } => branches
+                .iter()
+                .flat_map(|(condition, body)| [condition, body])
+                .chain(otherwise)
+                .collect(),
+            Compound::While { condition, body, .. } => vec![condition, body],
+            Compound::For { body, .. } => vec![body],
+            Compound::Case { arms, .. } => arms.iter().map(|arm| &arm.body).collect(),
+        }
+    }
+}
+
+// Commands print back as source text on one line, which is how `jobs` shows
+// them.
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let default_fd = if self.op == RedirectOp::Read { 0 } else { 1 };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        let op = Token::Redirect(None, self.op);
+        write!(f, "{op}{}", self.target)
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assignments = self.assignments.iter().map(|(name, value)| format!("{name}={value}"));
+        let redirects = self.redirects.iter().map(Redirect::to_string);
+        let parts: Vec<String> = assignments
+            .chain(self.words.iter().cloned())
+            .chain(redirects)
+            .collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(command) => write!(f, "{command}"),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{compound}")?;
+                redirects.iter().try_for_each(|redirect| write!(f, " {redirect}"))
+            }
+            Command::Function(name, body) => write!(f, "fn {name} {{ {body}; }}"),
+        }
+    }
+}
+
+impl fmt::Display for Compound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compound::Group(list) => write!(f, "{{ {list}; }}"),
+            Compound::Subshell(list) => write!(f, "({list})"),
+            Compound::If {
+                branches,
+                otherwise,
+            } => {
+                for (index, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if index == 0 { "if" } else { "elif" };
+                    write!(f, "{keyword} {condition}; then {body}; ")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {otherwise}; ")?;
+                }
+                write!(f, "fi")
+            }
+            Compound::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{keyword} {condition}; do {body}; done")
+            }
+            Compound::For { name, words, body } => {
+                write!(f, "for {name}")?;
+                if let Some(words) = words {
+                    write!(f, " in {}", words.join(" "))?;
+                }
+                write!(f, "; do {body}; done")
+            }
+            Compound::Case { word, arms } => {
+                write!(f, "case {word} in")?;
+                for arm in arms {
+                    write!(f, " {}) {};;", arm.patterns.join(" | "), arm.body)?;
+                }
+                write!(f, " esac")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        let commands: Vec<String> = self.commands.iter().map(Command::to_string).collect();
+        write!(f, "{}", commands.join(" | "))
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let connector = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {connector} {pipeline}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, item) in self.items.iter().enumerate() {
+            if index > 0 {
+                let previous = &self.items[index - 1];
+                write!(f, "{}", if previous.background { " " } else { "; " })?;
+            }
+            write!(f, "{item}")?;
+            if item.background {
+                write!(f, " &")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a command line or a whole script, expanding `aliases` at the head
+/// of each command. Returns `None` when there is nothing to run, such as blank
+/// lines or comments.
+pub fn parse(input: &str, aliases: &BTreeMap<String, String>) -> Result<Option<List>, ParseError> {
+    let (tokens, _) = expand_aliases(tokenize(input)?, aliases, &mut Vec::new())?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+    };
+
+    let list = parser.parse_list()?;
+    if let Some(token) = parser.tokens.next() {
+        return Err(ParseError::UnexpectedToken(token.to_string()));
+    }
+
+    Ok((!list.items.is_empty()).then_some(list))
+}
+
+/// How a command line that stops short goes on at the next prompt.
+#[derive(Debug, PartialEq)]
+pub enum Continuation {
+    /// A trailing backslash joins the next line onto this one.
+    Join,
+    /// The next line continues the same command, as inside quotes, after `|`
+    /// or `&&`, or in an `if` or `{` block that is still open.
+    Newline,
+}
+
+/// Whether `input` ends in the middle of a command, and how the rest should
+/// be added. Input that is simply wrong gives `None`, so it runs and reports
+/// its syntax error as usual.
+pub fn continuation(input: &str, aliases: &BTreeMap<String, String>) -> Option<Continuation> {
+    match parse(input, aliases) {
+        Err(ParseError::Lex(LexError::TrailingBackslash)) => Some(Continuation::Join),
+        Err(
+            ParseError::UnexpectedEnd
+            | ParseError::Lex(LexError::UnterminatedQuote(_)
+                | LexError::UnterminatedParen
+                | LexError::UnterminatedBrace),
+        ) => Some(Continuation::Newline),
+        _ => None,
+    }
+}
+
+/// Reserved words after which the next word is again a command name.
+pub fn starts_command(word: &str) -> bool {
+    matches!(
+        word,
+        "if" | "then" | "elif" | "else" | "while" | "until" | "do" | "{" | "!"
+    )
+}
+
+/// Reserved words that close a list, such as the `then` ending an `if`
+/// condition.
+fn ends_list(word: &str) -> bool {
+    matches!(
+        word,
+        "then" | "elif" | "else" | "fi" | "do" | "done" | "esac" | "}"
+    )
+}
+
+/// Whether `word` is a reserved word when it starts a command.
+pub fn is_reserved(word: &str) -> bool {
+    starts_command(word) || ends_list(word) || matches!(word, "for" | "case" | "fn")
+}
+
+/// Replaces unquoted alias names in command position with the tokens of their
+/// values, which may themselves start with aliases. An alias is never expanded
+/// inside its own expansion, and a value ending in a blank also makes the
+/// following word eligible, as in other shells. Returns the tokens and whether
+/// they end in command position.
+fn expand_aliases(
+    tokens: Vec<Token>,
+    aliases: &BTreeMap<String, String>,
+    active: &mut Vec<String>,
+) -> Result<(Vec<Token>, bool), ParseError> {
+    let mut out = Vec::new();
+    let mut at_head = true;
+    let mut after_redirect = false;
+
+    for token in tokens {
+        match token {
+            Token::Word(word) if after_redirect => {
+                after_redirect = false;
+                out.push(Token::Word(word));
+            }
+            Token::Word(word) if at_head && split_assignment(&word).is_none() => {
+                match aliases.get(&word) {
+                    _ if starts_command(&word) => out.push(Token::Word(word)),
+                    Some(value) if !active.contains(&word) => {
+                        active.push(word);
+                        let (expanded, ends_at_head) =
+                            expand_aliases(tokenize(value)?, aliases, active)?;
+                        active.pop();
+                        out.extend(expanded);
+                        at_head = ends_at_head || value.ends_with([' ', '\t']);
+                    }
+                    _ => {
+                        out.push(Token::Word(word));
+                        at_head = false;
+                    }
+                }
+            }
+            Token::Word(word) => {
+                // The body of `fn name {` starts a command, like any `{`.
+                if word == "{"
+                    && matches!(out.iter().rev().nth(1), Some(Token::Word(keyword)) if keyword == "fn")
+                {
+                    at_head = true;
+                }
+                out.push(Token::Word(word));
+            }
+            Token::Redirect(..) => {
+                after_redirect = true;
+                out.push(token);
+            }
+            _ => {
+                at_head = true;
+                out.push(token);
+            }
+        }
+    }
+
+    Ok((out, at_head))
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Parser {
+    /// Parses and-or lists up to the end of input or a token that closes the
+    /// enclosing construct, which is left for the caller.
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            let mut item = self.parse_and_or()?;
+            match self.tokens.peek() {
+                Some(Token::Semi | Token::Newline) => {}
+                Some(Token::Amp) => item.background = true,
+                _ => {
+                    items.push(item);
+                    break;
+                }
+            }
+            self.tokens.next();
+            items.push(item);
+        }
+
+        Ok(List { items })
+    }
+
+    /// Like `parse_list`, but the body of a control structure must not be
+    /// empty.
+    fn parse_body(&mut self) -> Result<List, ParseError> {
+        let list = self.parse_list()?;
+        if list.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(list)
+    }
+
+    fn at_list_end(&mut self) -> bool {
+        match self.tokens.peek() {
+            None | Some(Token::DoubleSemi | Token::RParen) => true,
+            Some(Token::Word(word)) => ends_list(word),
+            _ => false,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.tokens.next_if_eq(&Token::Newline).is_some() {}
+    }
+
+    fn peek_word(&mut self) -> Option<&str> {
+        match self.tokens.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// Consumes the reserved word `word` or fails.
+    fn expect(&mut self, word: &str) -> Result<(), ParseError> {
+        if self.peek_word() == Some(word) {
+            self.tokens.next();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// The error for whatever token comes next.
+    fn unexpected(&mut self) -> ParseError {
+        match self.tokens.next() {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.tokens.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.tokens.next();
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+
+        Ok(AndOr {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek_word() == Some("!");
+        if negated {
+            self.tokens.next();
+        }
+        let mut commands = vec![self.parse_command()?];
+
+        while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        let compound = match self.tokens.peek() {
+            Some(Token::LParen) => {
+                self.tokens.next();
+                let body = self.parse_body()?;
+                if self.tokens.next_if_eq(&Token::RParen).is_none() {
+                    return Err(self.unexpected());
+                }
+                Compound::Subshell(body)
+            }
+            Some(Token::Word(word)) => match word.as_str() {
+                "{" => {
+                    self.tokens.next();
+                    let body = self.parse_body()?;
+                    self.expect("}")?;
+                    Compound::Group(body)
+                }
+                "if" => self.parse_if()?,
+                "while" | "until" => self.parse_while()?,
+                "for" => self.parse_for()?,
+                "case" => self.parse_case()?,
+                "fn" => return self.parse_function(),
+                _ => return self.parse_simple_command().map(Command::Simple),
+            },
+            _ => return self.parse_simple_command().map(Command::Simple),
+        };
+
+        let mut redirects = Vec::new();
+        while let Some(&Token::Redirect(fd, op)) = self.tokens.peek() {
+            self.tokens.next();
+            redirects.push(self.parse_redirect(fd, op)?);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    fn parse_function(&mut self) -> Result<Command, ParseError> {
+        self.expect("fn")?;
+        let name = match self.tokens.next() {
+            Some(Token::Word(name)) if is_valid_function_name(&name) => name,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        self.skip_newlines();
+        self.expect("{")?;
+        let body = self.parse_body()?;
+        self.expect("}")?;
+        Ok(Command::Function(name, Arc::new(body)))
+    }
+
+    fn parse_if(&mut self) -> Result<Compound, ParseError> {
+        self.expect("if")?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.parse_body()?;
+            self.expect("then")?;
+            branches.push((condition, self.parse_body()?));
+
+            match self.peek_word() {
+                Some("elif") => {
+                    self.tokens.next();
+                }
+                Some("else") => {
+                    self.tokens.next();
+                    otherwise = Some(self.parse_body()?);
+                    self.expect("fi")?;
+                    break;
+                }
+                _ => {
+                    self.expect("fi")?;
+                    break;
+                }
+            }
+        }
+
+        Ok(Compound::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<Compound, ParseError> {
+        let until = self.peek_word() == Some("until");
+        self.tokens.next();
+        let condition = self.parse_body()?;
+        let body = self.parse_do_group()?;
+        Ok(Compound::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    fn parse_for(&mut self) -> Result<Compound, ParseError> {
+        self.expect("for")?;
+        let name = match self.tokens.next() {
+            Some(Token::Word(name)) if is_valid_name(&name) => name,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+
+        self.skip_newlines();
+        let mut words = None;
+        if self.peek_word() == Some("in") {
+            self.tokens.next();
+            let mut list = Vec::new();
+            while let Some(Token::Word(_)) = self.tokens.peek() {
+                if let Some(Token::Word(word)) = self.tokens.next() {
+                    list.push(word);
+                }
+            }
+            words = Some(list);
+        }
+        self.tokens
+            .next_if(|token| matches!(token, Token::Semi | Token::Newline));
+
+        let body = self.parse_do_group()?;
+        Ok(Compound::For { name, words, body })
+    }
+
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.skip_newlines();
+        self.expect("do")?;
+        let body = self.parse_body()?;
+        self.expect("done")?;
+        Ok(body)
+    }
+
+    fn parse_case(&mut self) -> Result<Compound, ParseError> {
+        self.expect("case")?;
+        let word = match self.tokens.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        self.skip_newlines();
+        self.expect("in")?;
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_word() == Some("esac") {
+                self.tokens.next();
+                break;
+            }
+
+            self.tokens.next_if_eq(&Token::LParen);
+            let mut patterns = Vec::new();
+            loop {
+                match self.tokens.next() {
+                    Some(Token::Word(pattern)) => patterns.push(pattern),
+                    Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                    None => return Err(ParseError::UnexpectedEnd),
+                }
+                if self.tokens.next_if_eq(&Token::Pipe).is_none() {
+                    break;
+                }
+            }
+            if self.tokens.next_if_eq(&Token::RParen).is_none() {
+                return Err(self.unexpected());
+            }
+
+            let body = self.parse_list()?;
+            arms.push(CaseArm { patterns, body });
+            // The last arm may leave out its `;;`.
+            if self.tokens.next_if_eq(&Token::DoubleSemi).is_none() {
+                self.skip_newlines();
+                self.expect("esac")?;
+                break;
+            }
+        }
+
+        Ok(Compound::Case { word, arms })
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.tokens.next() {
+                        match split_assignment(&word) {
+                            Some((name, value)) if command.words.is_empty() => command
+                                .assignments
+                                .push((name.to_string(), value.to_string())),
+                            _ => command.words.push(word),
+                        }
+                    }
+                }
+                Some(&Token::Redirect(fd, op)) => {
+                    self.tokens.next();
+                    command.redirects.push(self.parse_redirect(fd, op)?);
+                }
+                _ => break,
+            }
+        }
+
+        if command.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        Ok(command)
+    }
+
+    /// Parses the target following a redirect operator.
+    fn parse_redirect(&mut self, fd: Option<u32>, op: RedirectOp) -> Result<Redirect, ParseError> {
+        let target = match self.tokens.next() {
+            Some(Token::Word(word)) => word,
+            Some(other) => return Err(ParseError::UnexpectedToken(other.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        let fd = fd.unwrap_or(if op == RedirectOp::Read { 0 } else { 1 });
+        Ok(Redirect { fd, op, target })
+    }
+}
+
+/// Splits `NAME=value` into its parts if `NAME` is a valid variable name.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_valid_name(name).then_some((name, value))
+}
+
+/// Function names may also contain `-`, `.` and `:`, as in `deploy-staging`,
+/// but not be a reserved word.
+fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !starts_command(name)
+        && !ends_list(name)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parsed input printed back as one line of source.
+    fn reparse(input: &str, aliases: &BTreeMap<String, String>) -> String {
+        parse(input, aliases).unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn aliases_expand_in_command_position() {
+        let aliases: BTreeMap<String, String> = [
+            ("ll", "ls -l"),
+            ("la", "ll -a"),
+            ("ls", "ls --color"),
+            ("sudo", "sudo "),
+            ("both", "echo a; echo b"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+        assert_eq!(reparse("la x", &aliases), "ls --color -l -a x");
+        assert_eq!(reparse("echo ll; ll | ll && ll", &aliases), "echo ll; ls --color -l | ls --color -l && ls --color -l");
+        assert_eq!(reparse("sudo ll", &aliases), "sudo ls --color -l");
+        assert_eq!(reparse("X=1 ll >ll", &aliases), "X=1 ls --color -l >ll");
+        assert_eq!(reparse("'ll' \\ll", &aliases), "'ll' \\ll");
+        assert_eq!(reparse("if ll; then ll; fi", &aliases), "if ls --color -l; then ls --color -l; fi");
+        assert_eq!(reparse("both", &aliases), "echo a; echo b");
+    }
+
+    #[test]
+    fn control_structures() {
+        let aliases = BTreeMap::new();
+        for (input, printed) in [
+            ("if a\nthen b\nelif c; then d; else e; fi", "if a; then b; elif c; then d; else e; fi"),
+            ("while a; do b; c; done", "while a; do b; c; done"),
+            ("until a\ndo\n  b\ndone >log", "until a; do b; done >log"),
+            ("for x in a 'b c'; do echo $x; done", "for x in a 'b c'; do echo $x; done"),
+            ("for x; do echo $x; done", "for x; do echo $x; done"),
+            ("case $1 in a | b) x;; *) y; z;; esac", "case $1 in a | b) x;; *) y; z;; esac"),
+            ("case x in (a) b\nesac", "case x in a) b;; esac"),
+            ("{ a; b; } | (c && d) &", "{ a; b; } | (c && d) &"),
+            ("! if a; then while b; do c; done; fi", "! if a; then while b; do c; done; fi"),
+        ] {
+            assert_eq!(reparse(input, &aliases), printed, "{input}");
+        }
+
+        let list = parse("for x in 1 2; do echo $x; done", &aliases).unwrap().unwrap();
+        let Command::Compound(Compound::For { name, words, body }, _) = &list.items[0].first.commands[0] else {
+            panic!("not a for loop");
+        };
+        assert_eq!(name, "x");
+        assert_eq!(words.as_deref(), Some(&["1".to_string(), "2".to_string()][..]));
+        assert_eq!(body.to_string(), "echo $x");
+    }
+
+    #[test]
+    fn malformed_control_structures() {
+        let aliases = BTreeMap::new();
+        for input in ["fi", "if a; fi", "while a; done", "for 1 in a; do b; done", "case x a) b;; esac", "{ a; ) }"] {
+            assert!(matches!(parse(input, &aliases), Err(ParseError::UnexpectedToken(_))), "{input}");
+        }
+        for input in ["if a; then b", "while a; do", "case x in", "{ a;"] {
+            assert!(matches!(parse(input, &aliases), Err(ParseError::UnexpectedEnd)), "{input}");
+        }
+    }
+
+    #[test]
+    fn function_definitions() {
+        let aliases = BTreeMap::new();
+        assert_eq!(reparse("fn greet { echo hi $1; }", &aliases), "fn greet { echo hi $1; }");
+        assert_eq!(reparse("fn my-fn.x\n{\n  a\n  b\n}; my-fn.x", &aliases), "fn my-fn.x { a; b; }; my-fn.x");
+
+        let list = parse("fn f { g; }", &aliases).unwrap().unwrap();
+        let Command::Function(name, body) = &list.items[0].first.commands[0] else {
+            panic!("not a function");
+        };
+        assert_eq!(name, "f");
+        assert_eq!(body.to_string(), "g");
+
+        for input in ["fn { a; }", "fn if { a; }", "fn f a", "fn f { a; ) }"] {
+            assert!(matches!(parse(input, &aliases), Err(ParseError::UnexpectedToken(_))), "{input}");
+        }
+        assert!(matches!(parse("fn f { a;", &aliases), Err(ParseError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn unfinished_lines_ask_for_more() {
+        let aliases = BTreeMap::new();
+        assert_eq!(continuation("echo one \\", &aliases), Some(Continuation::Join));
+        for input in ["if true; then", "echo 'one", "echo $(date", "echo ${FOO", "echo one |"] {
+            assert_eq!(continuation(input, &aliases), Some(Continuation::Newline), "{input}");
+        }
+        assert_eq!(continuation("echo ${FOO}", &aliases), None);
+        assert_eq!(continuation("fi", &aliases), None);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::chat;
+use crate::shell::Shell;
+use std::ffi::CStr;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Used when `PS1` is unset. It keeps the old `user@heimdal:path$` look and
+/// adds the exit status, the time taken by slow commands, the git branch and
+/// any open chat session, each only when there is something to show.
+pub const DEFAULT_PS1: &str = concat!(
+    "\\[\\e[1;32m\\]\\u@heimdal\\[\\e[0m\\]:\\[\\e[1;34m\\]\\w\\[\\e[0m\\]",
+    "\\( \\[\\e[35m\\](\\g)\\[\\e[0m\\]\\)",
+    "\\( \\[\\e[36m\\][chat \\c]\\[\\e[0m\\]\\)",
+    "\\( \\[\\e[33m\\]\\D\\[\\e[0m\\]\\)",
+    "\\( \\[\\e[1;31m\\][\\?]\\[\\e[0m\\]\\)",
+    "\\$ ",
+);
+
+/// Commands quicker than this leave `\D` empty.
+const SLOW_COMMAND: Duration = Duration::from_secs(1);
+
+/// What the prompt is drawn from besides the shell itself.
+pub struct PromptContext {
+    /// How long the last command line took, if one has run.
+    pub duration: Option<Duration>,
+}
+
+/// Expands the escapes in a `PS1` template:
+///
+/// - `\u` user, `\h` host up to the first dot, `\H` full host name
+/// - `\w` working directory with `$HOME` shown as `~`, `\W` its last part
+/// - `\$` `#` for root and `$` otherwise, `\t` time as HH:MM:SS
+/// - `\n` newline, `\e` escape, `\a` bell, `\\` backslash
+/// - `\[` and `\]` are accepted for bash compatibility and dropped
+/// - `\g` git branch, with `*` when tracked files have changed
+/// - `\?` last exit status when it is not zero
+/// - `\D` time taken by the last command when it took a second or more
+/// - `\c` name of the open chat session
+/// - `\(` ... `\)` is left out unless a segment inside is non-empty
+pub fn render(template: &str, shell: &Shell, context: &PromptContext) -> String {
+    let chars: Vec<char> = template.chars().collect();
+    let mut pos = 0;
+    let mut out = String::new();
+    render_group(&chars, &mut pos, shell, context, &mut out);
+    out
+}
+
+/// Renders until the end of the template or a `\)`, returning whether any
+/// conditional segment produced text.
+fn render_group(
+    chars: &[char],
+    pos: &mut usize,
+    shell: &Shell,
+    context: &PromptContext,
+    out: &mut String,
+) -> bool {
+    let mut filled = false;
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        *pos += 1;
+        if c != '\\' || *pos == chars.len() {
+            out.push(c);
+            continue;
+        }
+        let escape = chars[*pos];
+        *pos += 1;
+        match escape {
+            '(' => {
+                let mut group = String::new();
+                if render_group(chars, pos, shell, context, &mut group) {
+                    out.push_str(&group);
+                    filled = true;
+                }
+            }
+            ')' => return filled,
+            'g' | '?' | 'D' | 'c' => {
+                let value = segment(escape, shell, context);
+                filled |= !value.is_empty();
+                out.push_str(&value);
+            }
+            'u' => out.push_str(shell.user()),
+            'h' => out.push_str(hostname().split('.').next().unwrap_or_default()),
+            'H' => out.push_str(&hostname()),
+            'w' => out.push_str(&working_directory(shell, false)),
+            'W' => out.push_str(&working_directory(shell, true)),
+            // SAFETY: geteuid has no preconditions.
+            '$' => out.push(if unsafe { libc::geteuid() } == 0 { '#' } else { '$' }),
+            't' => out.push_str(&clock()),
+            'n' => out.push('\n'),
+            'e' => out.push('\x1b'),
+            'a' => out.push('\x07'),
+            '\\' => out.push('\\'),
+            '[' | ']' => {}
+            other => {
+                out.push('\\');
+                out.push(other);
+            }
+        }
+    }
+    filled
+}
+
+fn segment(escape: char, shell: &Shell, context: &PromptContext) -> String {
+    match escape {
+        'g' => git_status(&working_dir()).unwrap_or_default(),
+        '?' if shell.last_status != 0 => shell.last_status.to_string(),
+        'D' => match context.duration {
+            Some(duration) if duration >= SLOW_COMMAND => format_duration(duration),
+            _ => String::new(),
+        },
+        'c' => chat::active_session().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn working_dir() -> PathBuf {
+    std::env::current_dir().unwrap_or_default()
+}
+
+fn working_directory(shell: &Shell, last_only: bool) -> String {
+    let path = working_dir();
+    if let Some(home) = shell.var("HOME").filter(|home| !home.is_empty()) {
+        if let Ok(rest) = path.strip_prefix(home) {
+            if rest.as_os_str().is_empty() {
+                return "~".to_string();
+            }
+            if !last_only {
+                return format!("~/{}", rest.display());
+            }
+        }
+    }
+    match path.file_name() {
+        Some(name) if last_only => name.to_string_lossy().into_owned(),
+        _ => path.display().to_string(),
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    // SAFETY: the buffer is writable for its full length, and the last byte
+    // stays zero so the result is always terminated.
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) } != 0 {
+        return String::new();
+    }
+    // SAFETY: terminated above.
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+fn clock() -> String {
+    // SAFETY: `tm` is plain data and localtime_r only writes to it.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+/// `4s`, `2m 5s`, `1h 3m`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// The branch checked out in the repository containing `dir`, or the short
+/// commit id when HEAD is detached, followed by `*` when the working tree has
+/// changes to tracked files. Read straight from `.git` so drawing the prompt
+/// never runs git.
+fn git_status(dir: &Path) -> Option<String> {
+    let (work_tree, git_dir) = find_repository(dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let mut status = match head.strip_prefix("ref: ") {
+        Some(reference) => reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string(),
+        None => head.chars().take(7).collect(),
+    };
+    if index_is_dirty(&work_tree, &git_dir) {
+        status.push('*');
+    }
+    Some(status)
+}
+
+/// Walks up from `dir` to the first `.git`, returning the work tree and the
+/// git directory. A `.git` file, as in worktrees and submodules, points to
+/// the real directory.
+fn find_repository(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some((ancestor.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let target = contents.trim().strip_prefix("gitdir: ")?;
+            return Some((ancestor.to_path_buf(), ancestor.join(target)));
+        }
+    }
+    None
+}
+
+const INDEX_ENTRY_HEADER: usize = 62;
+const EXTENDED_FLAG: u16 = 0x4000;
+const GITLINK_MODE: u32 = 0o160000;
+
+/// Drawing the prompt gives up on the `*` rather than stall in a large
+/// repository: past this many tracked files, or after this long, the tree
+/// counts as clean.
+const MAX_INDEX_ENTRIES: u32 = 20_000;
+const INDEX_BUDGET: Duration = Duration::from_millis(50);
+
+/// Compares each file in the index with the working tree the way `git
+/// status` does before looking at contents: a file that is missing or whose
+/// size or modification time differs from the one recorded counts as
+/// changed. Untracked files and staged changes are not looked at.
+fn index_is_dirty(work_tree: &Path, git_dir: &Path) -> bool {
+    let started = Instant::now();
+    let Ok(index) = fs::read(git_dir.join("index")) else {
+        return false;
+    };
+    if index.len() < 12 || &index[..4] != b"DIRC" {
+        return false;
+    }
+    let version = read_u32(&index, 4);
+    let count = read_u32(&index, 8);
+    if count > MAX_INDEX_ENTRIES {
+        return false;
+    }
+    let mut pos = 12;
+    let mut path: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let Some(header) = index.get(pos..pos + INDEX_ENTRY_HEADER) else {
+            return false;
+        };
+        let mtime = read_u32(header, 8) as i64;
+        let mtime_nsec = read_u32(header, 12) as i64;
+        let mode = read_u32(header, 24);
+        let size = read_u32(header, 36);
+        let flags = u16::from_be_bytes([header[60], header[61]]);
+        let mut name_start = pos + INDEX_ENTRY_HEADER;
+        if version >= 3 && flags & EXTENDED_FLAG != 0 {
+            name_start += 2;
+        }
+        let Some(name_len) = index.get(name_start..).and_then(|rest| rest.iter().position(|&b| b == 0)) else {
+            return false;
+        };
+        if version >= 4 {
+            // Paths are stored as the number of bytes to drop from the end
+            // of the previous path, then the new suffix. No padding.
+            let (strip, varint_len) = read_varint(&index[name_start..]);
+            path.truncate(path.len().saturating_sub(strip));
+            let suffix_start = name_start + varint_len;
+            let Some(suffix_len) = index[suffix_start..].iter().position(|&b| b == 0) else {
+                return false;
+            };
+            path.extend_from_slice(&index[suffix_start..suffix_start + suffix_len]);
+            pos = suffix_start + suffix_len + 1;
+        } else {
+            path.clear();
+            path.extend_from_slice(&index[name_start..name_start + name_len]);
+            // Entries are padded with 1 to 8 NULs to a multiple of 8 bytes.
+            let entry_len = name_start - pos + name_len;
+            pos += (entry_len + 8) & !7;
+        }
+        if mode == GITLINK_MODE {
+            continue;
+        }
+        let file = work_tree.join(String::from_utf8_lossy(&path).as_ref());
+        // Git built without nanosecond support records zero, so the
+        // sub-second part is only compared when it was stored.
+        match fs::symlink_metadata(&file) {
+            Ok(meta)
+                if meta.size() as u32 == size
+                    && meta.mtime() == mtime
+                    && (mtime_nsec == 0 || meta.mtime_nsec() == mtime_nsec) => {}
+            _ => return true,
+        }
+        if started.elapsed() > INDEX_BUDGET {
+            return false;
+        }
+    }
+    false
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Git's offset varint, returning the value and the bytes it took.
+fn read_varint(bytes: &[u8]) -> (usize, usize) {
+    let mut value = 0usize;
+    let mut len = 0;
+    for &byte in bytes {
+        if len > 0 {
+            value += 1;
+        }
+        value = (value << 7) | (byte & 0x7f) as usize;
+        len += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    (value, len)
+}
@@ -0,0 +1,154 @@
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Lists the files a replacement changed, one per line as
+/// `backup<TAB>size<TAB>seconds.nanoseconds<TAB>path`, giving the name of the
+/// saved original and what the file looked like once replaced.
+const INDEX: &str = "index";
+
+/// The originals of the files changed by a `search --replace`, kept so that
+/// `search --undo` can put them back. Each replacement starts a new journal,
+/// so only the last one can be undone.
+pub struct Journal {
+    dir: PathBuf,
+    index: File,
+    count: usize,
+}
+
+/// One line of the index.
+struct Entry {
+    backup: String,
+    size: u64,
+    mtime: (i64, i64),
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Starts a new journal in `dir`, dropping the previous one.
+    pub fn create(dir: &Path) -> io::Result<Journal> {
+        match fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        fs::create_dir_all(dir)?;
+        let index = File::create(dir.join(INDEX))?;
+        Ok(Journal {
+            dir: dir.to_path_buf(),
+            index,
+            count: 0,
+        })
+    }
+
+    /// Replaces the contents of `path`, which were `original`, with
+    /// `replaced`. The original and its index entry are saved in the journal
+    /// first, so a crash part way leaves nothing that cannot be undone.
+    pub fn replace(&mut self, path: &Path, original: &[u8], replaced: &[u8]) -> io::Result<()> {
+        let backup = self.count.to_string();
+        let mut file = File::create(self.dir.join(&backup))?;
+        file.write_all(original)?;
+        file.sync_all()?;
+
+        let index = &mut self.index;
+        replace_file(path, replaced, |new| {
+            // The renamed file keeps the temporary one's size and mtime.
+            let meta = new.metadata()?;
+            let mut line = format!("{backup}\t{}\t{}.{}\t", meta.len(), meta.mtime(), meta.mtime_nsec()).into_bytes();
+            line.extend_from_slice(path.as_os_str().as_bytes());
+            line.push(b'\n');
+            index.write_all(&line)?;
+            index.sync_all()
+        })?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// Puts back the files changed by the replacement recorded in `dir` and
+/// removes the journal. A file changed again since then is left alone, since
+/// restoring it would lose that change.
+pub fn undo(dir: &Path, out: &mut dyn Write, err: &mut dyn Write) -> io::Result<i32> {
+    let index = match fs::read(dir.join(INDEX)) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            writeln!(err, "search: nothing to undo")?;
+            return Ok(1);
+        }
+        Err(e) => return Err(e),
+    };
+    let mut status = 0;
+    for line in index.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+        let Some(entry) = Entry::parse(line) else {
+            writeln!(err, "search: the undo journal is damaged")?;
+            return Ok(1);
+        };
+        let shown = entry.path.display();
+        let unchanged = fs::metadata(&entry.path).is_ok_and(|meta| {
+            meta.len() == entry.size && (meta.mtime(), meta.mtime_nsec()) == entry.mtime
+        });
+        if !unchanged {
+            writeln!(err, "search: {shown}: changed since the replacement, not restored")?;
+            status = 1;
+            continue;
+        }
+        match fs::read(dir.join(&entry.backup)).and_then(|original| write_atomically(&entry.path, &original)) {
+            Ok(()) => writeln!(out, "restored {shown}")?,
+            Err(e) => {
+                writeln!(err, "search: {shown}: {e}")?;
+                status = 1;
+            }
+        }
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(status)
+}
+
+impl Entry {
+    fn parse(line: &[u8]) -> Option<Entry> {
+        let mut fields = line.splitn(4, |&b| b == b'\t');
+        let backup = std::str::from_utf8(fields.next()?).ok()?.to_string();
+        let size = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let (seconds, nanoseconds) = std::str::from_utf8(fields.next()?).ok()?.split_once('.')?;
+        let path = PathBuf::from(OsStr::from_bytes(fields.next()?));
+        Some(Entry {
+            backup,
+            size,
+            mtime: (seconds.parse().ok()?, nanoseconds.parse().ok()?),
+            path,
+        })
+    }
+}
+
+/// Replaces the contents of `path` through a temporary file renamed over it,
+/// so the file is never seen half written. The permissions are kept.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    replace_file(path, contents, |_| Ok(()))
+}
+
+/// Like [`write_atomically`], calling `before_rename` with the finished
+/// temporary file just before it takes the place of `path`.
+fn replace_file(
+    path: &Path,
+    contents: &[u8],
+    before_rename: impl FnOnce(&File) -> io::Result<()>,
+) -> io::Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.heimdal-{}", process::id()));
+    let result = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        file.write_all(contents)?;
+        file.set_permissions(permissions)?;
+        file.sync_all()?;
+        before_rename(&file)?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
@@ -0,0 +1,1003 @@
+use crate::commands::plural;
+use crate::glob;
+use crate::ignore::Ignore;
+use crate::replace::{self, Journal};
+use crate::signals;
+use crate::streams::Input;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::fs::{self, File, FileType};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+const USAGE: &str = "usage: search [-E] [-i] [-w] [-l | -c | -r TEXT] [-C N] [-g GLOB] [-t TYPE] \
+                     [-d DEPTH] [--hidden] [--no-ignore] PATTERN [PATH]
+       search --undo";
+
+/// The names `-t` accepts, with the globs each stands for.
+const TYPES: &[(&str, &[&str])] = &[
+    ("rust", &["*.rs"]),
+    ("toml", &["*.toml"]),
+    ("md", &["*.md", "*.markdown"]),
+];
+
+/// Larger files are skipped; they are almost always data rather than text
+/// anyone wants to read through.
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+// The colours grep uses by default.
+const PATH: &str = "\x1b[35m";
+const NUMBER: &str = "\x1b[32m";
+const SEPARATOR: &str = "\x1b[36m";
+const MATCH: &str = "\x1b[1;31m";
+const REMOVED: &str = "\x1b[31m";
+const ADDED: &str = "\x1b[32m";
+const ADDED_TEXT: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+/// A parsed `search` command line.
+struct Search {
+    regex: Regex,
+    root: String,
+    mode: Mode,
+    context: usize,
+    globs: Vec<Glob>,
+    max_depth: Option<usize>,
+    colour: bool,
+    hidden: bool,
+    use_ignore: bool,
+}
+
+/// What is printed for each file with a match.
+#[derive(PartialEq)]
+enum Mode {
+    Lines,
+    /// `-l`: only the path.
+    Files,
+    /// `-c`: the path and the number of matching lines.
+    Count,
+    /// `-r TEXT`: a preview of the lines with each match replaced by the
+    /// text, then the files rewritten once confirmed.
+    Replace(String),
+}
+
+/// The replacements planned in one file.
+struct Change {
+    path: PathBuf,
+    original: Vec<u8>,
+    replaced: String,
+    count: usize,
+    preview: Vec<u8>,
+}
+
+/// A `-g` glob. It is matched against the path below the starting directory
+/// when it contains a `/`, and against the file name otherwise.
+struct Glob {
+    pattern: glob::Pattern,
+    by_path: bool,
+    /// Written with a leading `!`: matching files and directories are left
+    /// out.
+    exclude: bool,
+}
+
+/// Prints every line under `PATH`, or the current directory, matching
+/// `PATTERN` as `path:line:column:text`. The pattern is plain text unless
+/// `-E` makes it a regular expression; `-i` ignores case, `-w` only matches
+/// whole words and `-C N` adds N lines of context around each match. `-l`
+/// prints just the names of the files that match and `-c` how many lines
+/// matched in each.
+///
+/// `-g GLOB` only searches files matching the glob, or skips them when it
+/// starts with `!`, and `-t TYPE` only searches one of the `TYPES`; both can
+/// be repeated. `-d DEPTH` stops that many directories down. Hidden files and
+/// those listed in `.gitignore` or `.ignore` files are left out unless
+/// `--hidden` or `--no-ignore` is given, and binary files are always skipped.
+///
+/// `-r TEXT` replaces every match with the text instead. Each file's changes
+/// are shown as a diff and written once confirmed, either file by file or
+/// for all the rest at once. The originals are kept so `search --undo` can
+/// put back the files changed by the last replacement.
+///
+/// Files are read on the blocking thread pool, several at once, and their
+/// results printed in path order as they come in. Returns 0 when something
+/// matched, 1 when nothing did and 2 on bad arguments or failed writes. A
+/// relative `PATH` is taken from `cwd`.
+pub async fn search(
+    args: &[String],
+    cwd: &Path,
+    colour: bool,
+    journal: Option<PathBuf>,
+    stdin: &Input,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let search = match Search::parse(args, colour) {
+        Ok(Some(search)) => Arc::new(search),
+        Ok(None) => {
+            return match journal {
+                Some(journal) => replace::undo(&journal, out, err),
+                None => {
+                    writeln!(err, "search: nothing to undo")?;
+                    Ok(1)
+                }
+            };
+        }
+        Err(e) => {
+            writeln!(err, "search: {e}")?;
+            writeln!(err, "{USAGE}")?;
+            return Ok(2);
+        }
+    };
+    let root = match cwd.join(&search.root).canonicalize() {
+        Ok(root) => root,
+        Err(e) => {
+            writeln!(err, "search: {}: {e}", search.root)?;
+            return Ok(2);
+        }
+    };
+    if let Mode::Replace(_) = search.mode {
+        let Some(journal) = journal else {
+            writeln!(err, "search: --replace needs HOME or XDG_STATE_HOME to keep its undo journal")?;
+            return Ok(2);
+        };
+        return replace_files(&search, &root, &journal, stdin, out, err).await;
+    }
+
+    let mut found = false;
+    let separator = search.paint(SEPARATOR, "--");
+    let finished = scan(&search, &root, Search::search_file, err, |_, output, _| {
+        if !output.is_empty() {
+            if found && search.context > 0 {
+                writeln!(out, "{separator}")?;
+            }
+            out.write_all(&output)?;
+            found = true;
+        }
+        Ok(true)
+    })
+    .await?;
+    if !finished {
+        return Ok(130);
+    }
+    Ok(if found { 0 } else { 1 })
+}
+
+/// Runs `work` on every file the walk yields, several at once on the
+/// blocking thread pool, and hands the results to `each` in walk order until
+/// it returns false. Errors reading a file are reported instead. Returns
+/// false when interrupted.
+async fn scan<T: Send + 'static>(
+    search: &Arc<Search>,
+    root: &Path,
+    work: fn(&Search, &Path, &str) -> io::Result<T>,
+    err: &mut dyn Write,
+    mut each: impl FnMut(String, T, &mut dyn Write) -> io::Result<bool>,
+) -> io::Result<bool> {
+    let mut walk = Walk::new(root.to_path_buf(), Arc::clone(search));
+    let window = thread::available_parallelism().map_or(4, NonZero::get) * 2;
+    let mut pending = VecDeque::new();
+    loop {
+        while pending.len() < window {
+            match walk.next() {
+                Some(Ok(path)) => {
+                    let search = Arc::clone(search);
+                    let shown = search.display_path(root, &path);
+                    pending.push_back(tokio::task::spawn_blocking(move || {
+                        let result = work(&search, &path, &shown);
+                        (shown, result)
+                    }));
+                }
+                Some(Err((path, e))) => {
+                    writeln!(err, "search: {}: {e}", search.display_path(root, &path))?
+                }
+                None => break,
+            }
+        }
+        let Some(task) = pending.pop_front() else {
+            return Ok(true);
+        };
+        let result = task.await;
+        if signals::interrupted() {
+            return Ok(false);
+        }
+        match result {
+            Ok((shown, Ok(value))) => {
+                if !each(shown, value, err)? {
+                    return Ok(true);
+                }
+            }
+            Ok((shown, Err(e))) => writeln!(err, "search: {shown}: {e}")?,
+            Err(_) => {}
+        }
+    }
+}
+
+/// Shows the replacements in each file and, once they are confirmed, writes
+/// them, keeping the originals in `journal_dir` for `search --undo`.
+async fn replace_files(
+    search: &Arc<Search>,
+    root: &Path,
+    journal_dir: &Path,
+    stdin: &Input,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let mut journal = None;
+    let mut found = false;
+    let mut confirm = true;
+    let mut failed = false;
+    let (mut files, mut matches) = (0, 0);
+    let finished = scan(search, root, Search::plan_file, err, |shown, change, err| {
+        let Some(change) = change else {
+            return Ok(true);
+        };
+        found = true;
+        out.write_all(&change.preview)?;
+        out.flush()?;
+        if confirm {
+            let question = format!(
+                "Replace {} in {shown}? [y]es, [n]o, [a]ll, [q]uit ",
+                plural(change.count, "match", "matches"),
+            );
+            match ask(&question, stdin, &signals::interrupted, err)? {
+                Some('y') => {}
+                Some('n') => return Ok(true),
+                Some('a') => confirm = false,
+                _ => return Ok(false),
+            }
+        }
+        let result = fs::read(&change.path).and_then(|current| {
+            if current != change.original {
+                return Err(io::Error::other("changed since it was searched, not replaced"));
+            }
+            if journal.is_none() {
+                journal = Some(Journal::create(journal_dir)?);
+            }
+            let journal = journal.as_mut().expect("created above");
+            journal.replace(&change.path, &change.original, change.replaced.as_bytes())
+        });
+        match result {
+            Ok(()) => {
+                files += 1;
+                matches += change.count;
+            }
+            Err(e) => {
+                writeln!(err, "search: {shown}: {e}")?;
+                failed = true;
+            }
+        }
+        Ok(true)
+    })
+    .await?;
+
+    if files > 0 {
+        writeln!(
+            out,
+            "Replaced {} in {}; `search --undo` puts them back.",
+            plural(matches, "match", "matches"),
+            plural(files, "file", "files"),
+        )?;
+    }
+    if !finished || signals::interrupted() {
+        return Ok(130);
+    }
+    Ok(match (failed, found) {
+        (true, _) => 2,
+        (false, true) => 0,
+        (false, false) => 1,
+    })
+}
+
+/// Writes `question` to `err` until `stdin` answers with one of `y`, `n`,
+/// `a` or `q`, returning the letter, or `None` at the end of input or once
+/// `interrupted` says Ctrl-C was pressed.
+fn ask(
+    question: &str,
+    stdin: &Input,
+    interrupted: &dyn Fn() -> bool,
+    err: &mut dyn Write,
+) -> io::Result<Option<char>> {
+    loop {
+        write!(err, "{question}")?;
+        err.flush()?;
+        let mut line = Vec::new();
+        loop {
+            match read_byte(stdin.as_raw_fd(), interrupted)? {
+                Answer::Byte(b'\n') => break,
+                Answer::Byte(byte) => line.push(byte),
+                Answer::End if line.is_empty() => return Ok(None),
+                Answer::End => break,
+                Answer::Interrupted => return Ok(None),
+            }
+        }
+        let answer = String::from_utf8_lossy(&line).trim().to_lowercase();
+        if let Some(letter) = answer.chars().next().filter(|letter| "ynaq".contains(*letter)) {
+            return Ok(Some(letter));
+        }
+    }
+}
+
+enum Answer {
+    Byte(u8),
+    End,
+    Interrupted,
+}
+
+/// How often a prompt waiting for input checks for Ctrl-C. The shell's
+/// SIGINT handler restarts interrupted reads, so the read itself would keep
+/// waiting.
+const INTERRUPT_POLL: libc::c_int = 100;
+
+/// Reads one byte straight from `fd`, so nothing past the answer is taken
+/// from an input the commands after `search` still read.
+fn read_byte(fd: RawFd, interrupted: &dyn Fn() -> bool) -> io::Result<Answer> {
+    let mut poll = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        if interrupted() {
+            return Ok(Answer::Interrupted);
+        }
+        // SAFETY: `poll` is a single valid pollfd.
+        match unsafe { libc::poll(&mut poll, 1, INTERRUPT_POLL) } {
+            0 => continue,
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            _ => {}
+        }
+        let mut byte = 0u8;
+        // SAFETY: `byte` is writable for the one byte asked for.
+        match unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) } {
+            0 => return Ok(Answer::End),
+            1 => return Ok(Answer::Byte(byte)),
+            _ => {
+                let e = io::Error::last_os_error();
+                if !matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock) {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Walks the files below a directory in name order, leaving out hidden
+/// entries, those matched by ignore files and those the `-g` and `-t` filters
+/// reject, and going no deeper than `-d` allows. `.git` is always left out.
+/// Symbolic links are not followed. A file given as the root is the only one
+/// yielded, whatever the filters say.
+struct Walk {
+    root: PathBuf,
+    search: Arc<Search>,
+    // The directories being listed, innermost last, with their remaining
+    // entries in reverse order and the ignore rules inside them.
+    stack: Vec<(Vec<(PathBuf, FileType)>, Ignore)>,
+    first: Option<Result<PathBuf, (PathBuf, io::Error)>>,
+}
+
+impl Walk {
+    fn new(root: PathBuf, search: Arc<Search>) -> Walk {
+        let mut walk = Walk {
+            root,
+            search,
+            stack: Vec::new(),
+            first: None,
+        };
+        if walk.root.is_file() {
+            walk.first = Some(Ok(walk.root.clone()));
+            return walk;
+        }
+        // Depth counts from the named path, so `-d 0` searches only that
+        // path and a directory gives nothing.
+        if walk.search.max_depth == Some(0) {
+            return walk;
+        }
+        let ignore = if walk.search.use_ignore {
+            Ignore::for_root(&walk.root)
+        } else {
+            Ignore::default()
+        };
+        match list_dir(&walk.root) {
+            Ok(entries) => walk.stack.push((entries, ignore)),
+            Err(e) => walk.first = Some(Err((walk.root.clone(), e))),
+        }
+        walk
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, (PathBuf, io::Error)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        loop {
+            // Of the entry, counting the root's own entries as 1.
+            let depth = self.stack.len();
+            let (entries, ignore) = self.stack.last_mut()?;
+            let Some((path, file_type)) = entries.pop() else {
+                self.stack.pop();
+                continue;
+            };
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name == ".git" || (!self.search.hidden && name.starts_with('.')) {
+                continue;
+            }
+            let is_dir = file_type.is_dir();
+            if self.search.use_ignore && ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+            if !self.search.admits(relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if self.search.max_depth.is_some_and(|max| depth >= max) {
+                    continue;
+                }
+                let ignore = if self.search.use_ignore {
+                    ignore.child(&path)
+                } else {
+                    Ignore::default()
+                };
+                match list_dir(&path) {
+                    Ok(entries) => self.stack.push((entries, ignore)),
+                    Err(e) => return Some(Err((path, e))),
+                }
+            } else if file_type.is_file() {
+                return Some(Ok(path));
+            }
+        }
+    }
+}
+
+/// The entries of `dir`, sorted by name in reverse so the first comes off
+/// the end.
+fn list_dir(dir: &Path) -> io::Result<Vec<(PathBuf, FileType)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        entries.push((entry.path(), entry.file_type()?));
+    }
+    entries.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(entries)
+}
+
+/// What one file has printed so far, with the lines held back in case a
+/// later match needs them as context.
+struct FileOutput {
+    out: Vec<u8>,
+    before: VecDeque<(usize, String)>,
+    after: usize,
+    last: Option<usize>,
+}
+
+impl Search {
+    /// Parses the command line, giving `None` for `search --undo`.
+    fn parse(args: &[String], colour: bool) -> Result<Option<Search>, String> {
+        let arg_count = args.len();
+        let mut undo = false;
+        let mut ignore_case = false;
+        let mut whole_word = false;
+        let mut is_regex = false;
+        let mut mode = Mode::Lines;
+        let mut context = 0;
+        let mut globs = Vec::new();
+        let mut max_depth = None;
+        let mut hidden = false;
+        let mut use_ignore = true;
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        let mut options_done = false;
+        while let Some(arg) = args.next() {
+            if options_done || !arg.starts_with('-') || arg == "-" {
+                if positional.len() == 2 {
+                    return Err(format!("unexpected argument `{arg}`"));
+                }
+                positional.push(arg.clone());
+                continue;
+            }
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let mut value = || {
+                    inline
+                        .clone()
+                        .or_else(|| args.next().cloned())
+                        .ok_or_else(|| format!("--{name} needs a value"))
+                };
+                match name {
+                    "context" => context = parse_count("--context", &value()?)?,
+                    "glob" => globs.push(Glob::parse(value()?)),
+                    "type" => add_type(&mut globs, &value()?)?,
+                    "max-depth" => max_depth = Some(parse_count("--max-depth", &value()?)?),
+                    "replace" => mode = Mode::Replace(value()?),
+                    _ if inline.is_some() => return Err(format!("unknown option `{arg}`")),
+                    "" => options_done = true,
+                    "regex" => is_regex = true,
+                    "ignore-case" => ignore_case = true,
+                    "word" => whole_word = true,
+                    "files-with-matches" => mode = Mode::Files,
+                    "count" => mode = Mode::Count,
+                    "hidden" => hidden = true,
+                    "no-ignore" => use_ignore = false,
+                    "undo" => undo = true,
+                    _ => return Err(format!("unknown option `{arg}`")),
+                }
+                continue;
+            }
+            for (index, c) in arg.char_indices().skip(1) {
+                match c {
+                    'E' => is_regex = true,
+                    'i' => ignore_case = true,
+                    'w' => whole_word = true,
+                    'l' => mode = Mode::Files,
+                    'c' => mode = Mode::Count,
+                    'C' | 'g' | 't' | 'd' | 'r' => {
+                        // The value may follow directly, as in `-C2`.
+                        let rest = &arg[index + 1..];
+                        let value = if rest.is_empty() {
+                            args.next().cloned().ok_or_else(|| format!("-{c} needs a value"))?
+                        } else {
+                            rest.to_string()
+                        };
+                        match c {
+                            'C' => context = parse_count("-C", &value)?,
+                            'g' => globs.push(Glob::parse(value)),
+                            't' => add_type(&mut globs, &value)?,
+                            'r' => mode = Mode::Replace(value),
+                            _ => max_depth = Some(parse_count("-d", &value)?),
+                        }
+                        break;
+                    }
+                    _ => return Err(format!("unknown option `-{c}`")),
+                }
+            }
+        }
+
+        if undo {
+            if arg_count > 1 {
+                return Err("--undo takes no other arguments".to_string());
+            }
+            return Ok(None);
+        }
+
+        let mut positional = positional.into_iter();
+        let pattern = positional.next().ok_or("missing pattern")?;
+        let root = positional.next().unwrap_or_else(|| ".".to_string());
+        let source = if is_regex { pattern } else { regex::escape(&pattern) };
+        let regex = compile(&source, ignore_case, whole_word).map_err(|e| format!("invalid pattern: {e}"))?;
+        if mode != Mode::Lines {
+            context = 0;
+        }
+        Ok(Some(Search {
+            regex,
+            root,
+            mode,
+            context,
+            globs,
+            max_depth,
+            colour,
+            hidden,
+            use_ignore,
+        }))
+    }
+
+    /// Whether the `-g` and `-t` filters let a file or directory through.
+    /// Only exclusions apply to directories, so that the files wanted inside
+    /// them are still found.
+    fn admits(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.to_string_lossy();
+        let name = relative.file_name().unwrap_or_default().to_string_lossy();
+        let matches = |glob: &&Glob| {
+            let subject = if glob.by_path { &path } else { &name };
+            glob.pattern.matches(subject)
+        };
+        let (excludes, includes): (Vec<&Glob>, Vec<&Glob>) =
+            self.globs.iter().partition(|glob| glob.exclude);
+        if excludes.iter().any(matches) {
+            return false;
+        }
+        is_dir || includes.is_empty() || includes.iter().any(matches)
+    }
+
+    /// Paths are shown below the starting directory as it was given, or
+    /// relative to it when that is the current directory.
+    fn display_path(&self, root: &Path, path: &Path) -> String {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        if relative.as_os_str().is_empty() {
+            return self.root.clone();
+        }
+        if self.root == "." {
+            return relative.display().to_string();
+        }
+        Path::new(&self.root).join(relative).display().to_string()
+    }
+
+    /// Searches one file, returning what to print for it, which is empty
+    /// when nothing matched. The file is read a line at a time. Files over
+    /// `MAX_FILE_SIZE` are skipped, and so are binary files, recognised by a
+    /// NUL byte as git does.
+    fn search_file(&self, path: &Path, shown: &str) -> io::Result<Vec<u8>> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() > MAX_FILE_SIZE {
+            return Ok(Vec::new());
+        }
+        let mut reader = BufReader::new(file);
+        if reader.fill_buf()?.contains(&0) {
+            return Ok(Vec::new());
+        }
+        let mut output = FileOutput {
+            out: Vec::new(),
+            before: VecDeque::new(),
+            after: 0,
+            last: None,
+        };
+        let mut bytes = Vec::new();
+        let mut number = 0;
+        let mut count = 0;
+        loop {
+            bytes.clear();
+            if reader.read_until(b'\n', &mut bytes)? == 0 {
+                break;
+            }
+            if bytes.contains(&0) || signals::interrupted() {
+                return Ok(Vec::new());
+            }
+            number += 1;
+            while bytes.last().is_some_and(|b| matches!(b, b'\n' | b'\r')) {
+                bytes.pop();
+            }
+            let line = String::from_utf8_lossy(&bytes);
+            let found = self.find_all(&line);
+            match self.mode {
+                Mode::Lines => self.print_line(shown, number, &line, &found, &mut output)?,
+                Mode::Files if !found.is_empty() => {
+                    writeln!(output.out, "{}", self.paint(PATH, shown))?;
+                    break;
+                }
+                Mode::Files => {}
+                Mode::Count => count += usize::from(!found.is_empty()),
+                Mode::Replace(_) => unreachable!("replacements are planned by plan_file"),
+            }
+        }
+        if count > 0 {
+            let colon = self.paint(SEPARATOR, ":");
+            writeln!(output.out, "{}{colon}{count}", self.paint(PATH, shown))?;
+        }
+        Ok(output.out)
+    }
+
+    /// Prints a matching line with the context held back before it, or a
+    /// line of context after an earlier match, or holds the line back.
+    fn print_line(
+        &self,
+        path: &str,
+        number: usize,
+        line: &str,
+        found: &[(usize, usize)],
+        output: &mut FileOutput,
+    ) -> io::Result<()> {
+        let out = &mut output.out;
+        if found.is_empty() {
+            if output.after > 0 {
+                output.after -= 1;
+                output.last = Some(number);
+                self.print_context(path, number, line, out)?;
+            } else if self.context > 0 {
+                output.before.push_back((number, line.to_string()));
+                if output.before.len() > self.context {
+                    output.before.pop_front();
+                }
+            }
+            return Ok(());
+        }
+
+        let first = output.before.front().map_or(number, |(number, _)| *number);
+        if self.context > 0 && output.last.is_some_and(|last| last + 1 < first) {
+            writeln!(out, "{}", self.paint(SEPARATOR, "--"))?;
+        }
+        for (number, line) in output.before.drain(..) {
+            self.print_context(path, number, &line, out)?;
+        }
+        let column = line[..found[0].0].chars().count() + 1;
+        let colon = self.paint(SEPARATOR, ":");
+        writeln!(
+            out,
+            "{}{colon}{}{colon}{}{colon}{}",
+            self.paint(PATH, path),
+            self.paint(NUMBER, &number.to_string()),
+            self.paint(NUMBER, &column.to_string()),
+            self.highlight(line, found),
+        )?;
+        output.after = self.context;
+        output.last = Some(number);
+        Ok(())
+    }
+
+    fn print_context(&self, path: &str, number: usize, line: &str, out: &mut Vec<u8>) -> io::Result<()> {
+        let dash = self.paint(SEPARATOR, "-");
+        writeln!(
+            out,
+            "{}{dash}{}{dash}{line}",
+            self.paint(PATH, path),
+            self.paint(NUMBER, &number.to_string()),
+        )
+    }
+
+    /// Works out the replacements in one file and the diff showing them, or
+    /// `None` when nothing matched. Files skipped by `search_file` are
+    /// skipped here too, along with any that are not UTF-8.
+    fn plan_file(&self, path: &Path, shown: &str) -> io::Result<Option<Change>> {
+        let Mode::Replace(replacement) = &self.mode else {
+            return Ok(None);
+        };
+        if fs::metadata(path)?.len() > MAX_FILE_SIZE {
+            return Ok(None);
+        }
+        let original = fs::read(path)?;
+        if original.contains(&0) {
+            return Ok(None);
+        }
+        let Ok(text) = std::str::from_utf8(&original) else {
+            return Ok(None);
+        };
+
+        let mut replaced = String::with_capacity(text.len());
+        let mut preview = Vec::new();
+        let mut count = 0;
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            if signals::interrupted() {
+                return Ok(None);
+            }
+            let content = line.trim_end_matches(['\n', '\r']);
+            let found = self.find_all(content);
+            if found.is_empty() {
+                replaced.push_str(line);
+                continue;
+            }
+            let mut new_line = String::new();
+            let mut added = Vec::new();
+            let mut end = 0;
+            for &(start, stop) in &found {
+                new_line.push_str(&content[end..start]);
+                added.push((new_line.len(), new_line.len() + replacement.len()));
+                new_line.push_str(replacement);
+                end = stop;
+            }
+            new_line.push_str(&content[end..]);
+            replaced.push_str(&new_line);
+            replaced.push_str(&line[content.len()..]);
+
+            if count == 0 {
+                writeln!(preview, "{}", self.paint(PATH, shown))?;
+            }
+            count += found.len();
+            let number = index + 1;
+            writeln!(preview, "{}", self.diff_line(number, '-', REMOVED, MATCH, content, &found))?;
+            writeln!(preview, "{}", self.diff_line(number, '+', ADDED, ADDED_TEXT, &new_line, &added))?;
+        }
+        if count == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Change {
+            path: path.to_path_buf(),
+            original,
+            replaced,
+            count,
+            preview,
+        }))
+    }
+
+    /// A line of the replacement diff, in `colour` with the matched or
+    /// inserted text in `strong`.
+    fn diff_line(
+        &self,
+        number: usize,
+        sign: char,
+        colour: &str,
+        strong: &str,
+        line: &str,
+        spans: &[(usize, usize)],
+    ) -> String {
+        let mut text = format!(
+            "{} {}",
+            self.paint(NUMBER, &format!("{number:>5}")),
+            self.paint(colour, &sign.to_string()),
+        );
+        let mut end = 0;
+        for &(start, stop) in spans {
+            if start > end {
+                text.push_str(&self.paint(colour, &line[end..start]));
+            }
+            if stop > start {
+                text.push_str(&self.paint(strong, &line[start..stop]));
+            }
+            end = stop;
+        }
+        if end < line.len() {
+            text.push_str(&self.paint(colour, &line[end..]));
+        }
+        text
+    }
+
+    fn highlight(&self, line: &str, found: &[(usize, usize)]) -> String {
+        if !self.colour {
+            return line.to_string();
+        }
+        let mut out = String::new();
+        let mut end = 0;
+        for &(start, stop) in found {
+            out.push_str(&line[end..start]);
+            out.push_str(&self.paint(MATCH, &line[start..stop]));
+            end = stop;
+        }
+        out.push_str(&line[end..]);
+        out
+    }
+
+    /// Byte ranges of the matches in `text`, leftmost first.
+    fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        self.regex.find_iter(text).map(|found| (found.start(), found.end())).collect()
+    }
+
+    fn paint(&self, colour: &str, text: &str) -> String {
+        if self.colour {
+            format!("{colour}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+impl Glob {
+    fn parse(glob: String) -> Glob {
+        let (pattern, exclude) = match glob.strip_prefix('!') {
+            Some(pattern) => (pattern, true),
+            None => (glob.as_str(), false),
+        };
+        Glob {
+            by_path: pattern.contains('/'),
+            pattern: glob::Pattern::new(pattern),
+            exclude,
+        }
+    }
+}
+
+/// Compiles a pattern. With `whole_word`, like `grep -w`, it only matches
+/// where the text around the match is not a word character.
+fn compile(source: &str, ignore_case: bool, whole_word: bool) -> Result<Regex, regex::Error> {
+    let build = |source: &str| RegexBuilder::new(source).case_insensitive(ignore_case).build();
+    // Checked on its own first, since wrapping it can make a broken pattern
+    // such as `a)(b` valid.
+    let regex = build(source)?;
+    if !whole_word {
+        return Ok(regex);
+    }
+    build(&format!(r"\b{{start-half}}(?:{source})\b{{end-half}}"))
+}
+
+/// Adds the globs for a `-t` type.
+fn add_type(globs: &mut Vec<Glob>, name: &str) -> Result<(), String> {
+    let Some((_, patterns)) = TYPES.iter().find(|(type_name, _)| *type_name == name) else {
+        let known: Vec<&str> = TYPES.iter().map(|(type_name, _)| *type_name).collect();
+        return Err(format!("unknown type `{name}` (known: {})", known.join(", ")));
+    };
+    globs.extend(patterns.iter().map(|pattern| Glob::parse(pattern.to_string())));
+    Ok(())
+}
+
+fn parse_count(option: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{option}: invalid number `{value}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Option<Search>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Search::parse(&args, false)
+    }
+
+    #[test]
+    fn undo_is_parsed_as_an_option() {
+        assert!(matches!(parse(&["--undo"]), Ok(None)));
+        assert!(parse(&["--undo", "foo"]).is_err());
+        assert!(parse(&["foo", "--undo"]).is_err());
+
+        let search = parse(&["--", "--undo"]).unwrap().unwrap();
+        assert_eq!(search.root, ".");
+        let search = parse(&["foo", "--replace", "--undo"]).unwrap().unwrap();
+        assert!(search.mode == Mode::Replace("--undo".to_string()));
+    }
+
+    fn matches<'t>(args: &[&str], text: &'t str) -> Vec<&'t str> {
+        let search = parse(args).unwrap().unwrap();
+        search.find_all(text).into_iter().map(|(from, to)| &text[from..to]).collect()
+    }
+
+    #[test]
+    fn patterns_follow_the_options() {
+        assert_eq!(matches(&["a.c"], "abc a.c"), ["a.c"]);
+        assert_eq!(matches(&["-E", "a.c"], "abc a.c"), ["abc", "a.c"]);
+        assert_eq!(matches(&["-i", "hello"], "Hello HELLO"), ["Hello", "HELLO"]);
+        assert_eq!(matches(&["-w", "cat"], "concat cat_ cat."), ["cat"]);
+        assert_eq!(matches(&["-w", "-E", "a."], "ab a- xa-"), ["ab", "a-"]);
+        assert!(parse(&["-E", "(a"]).is_err());
+        assert!(parse(&["-w", "-E", "a)(b"]).is_err());
+    }
+
+    #[test]
+    fn depth_counts_from_the_root() {
+        let root = std::env::temp_dir().join(format!("heimdal-search-depth-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        for file in ["top", "a/middle", "a/b/bottom"] {
+            fs::write(root.join(file), "x\n").unwrap();
+        }
+        let found = |depth: &str| -> Vec<String> {
+            let search = parse(&["-d", depth, "x"]).unwrap().unwrap();
+            let walk = Walk::new(root.clone(), Arc::new(search));
+            walk.map(|path| path.unwrap().strip_prefix(&root).unwrap().display().to_string()).collect()
+        };
+        let results = [found("0"), found("1"), found("2")];
+        let file = Walk::new(root.join("top"), Arc::new(parse(&["-d", "0", "x"]).unwrap().unwrap())).count();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(results[0].is_empty());
+        assert_eq!(results[1], ["top"]);
+        assert_eq!(results[2], ["a/middle", "top"]);
+        assert_eq!(file, 1);
+    }
+
+    #[test]
+    fn ask_takes_only_the_answer_from_its_input() {
+        let (reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"maybe\n y \nrest\n").unwrap();
+        drop(writer);
+        let mut stdin = Input::Pipe(reader);
+        let mut err = Vec::new();
+
+        assert_eq!(ask("Go? ", &stdin, &|| false, &mut err).unwrap(), Some('y'));
+        assert_eq!(err, b"Go? Go? ");
+        let mut rest = String::new();
+        stdin.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "rest\n");
+        assert_eq!(ask("Go? ", &stdin, &|| false, &mut err).unwrap(), None);
+    }
+
+    #[test]
+    fn ctrl_c_aborts_ask() {
+        // The writer stays open, so only the interrupt can end the wait.
+        let (reader, _writer) = io::pipe().unwrap();
+        let stdin = Input::Pipe(reader);
+        let interrupted = AtomicBool::new(false);
+
+        let answer = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(200));
+                interrupted.store(true, Ordering::Relaxed);
+            });
+            ask("Go? ", &stdin, &|| interrupted.load(Ordering::Relaxed), &mut Vec::new()).unwrap()
+        });
+        assert_eq!(answer, None);
+    }
+}
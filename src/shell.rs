@@ -1,34 +1,285 @@
-use crate::chat;
 use crate::chat::{ChatConfig, ChatRole};
-use env::{set_current_dir, var};
+use crate::executor::run_list;
+use crate::jobs::Jobs;
+use crate::signals;
+use crate::streams::{HeldFds, Io};
+use crate::commands::print_help;
+use crate::parser::{parse, List};
+use crate::prompt::{self, PromptContext, DEFAULT_PS1};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::path::Path;
-use std::env::{current_dir};
-use tokio::process::Command;
-use crate::commands::{get_os, print_fortune, print_help, print_tree, search_files};
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 use crate::commands_enum::commands_enum::ShellCommand;
+use crate::history::History;
+use crate::editor::{AbbreviationHandler, EditorHelper};
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::history::DefaultHistory;
+use rustyline::{
+    CompletionType, Config, Editor, EventHandler, KeyCode, KeyEvent, Modifiers,
+};
 
-fn parse_command(input: &str) -> ShellCommand {
-    let mut parts = input.trim().split_whitespace();
-    let command = parts.next().unwrap_or("");
-    let args: Vec<String> = parts.map(|s| s.to_string()).collect();
+/// Startup file shared by every user, run before `~/.heimdalrc`.
+const SYSTEM_RC: &str = "/etc/heimdalrc";
 
-    match command {
-        "exit" => ShellCommand::Exit,
+#[derive(Clone)]
+pub struct Variable {
+    pub value: String,
+    /// Exported variables are passed on to external commands.
+    pub exported: bool,
+}
+
+/// Pending `break` or `continue`, with the number of loops it still has to
+/// leave, or `return`. Lists stop running commands until the loop or function
+/// it targets handles it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break(usize),
+    Continue(usize),
+    Return,
+}
+
+/// State that outlives a single command line.
+#[derive(Clone)]
+pub struct Shell {
+    pub vars: HashMap<String, Variable>,
+    pub history: History,
+    pub aliases: BTreeMap<String, String>,
+    pub functions: BTreeMap<String, Arc<List>>,
+    /// For each running function, the values its `local` variables hid, to
+    /// be restored when it returns.
+    scopes: Vec<HashMap<String, Option<Variable>>>,
+    /// Abbreviations expanded in the line editor as they are typed.
+    pub abbreviations: BTreeMap<String, String>,
+    /// Script name and arguments, available as `$0`, `$1`... and `$#`.
+    pub arg0: String,
+    pub args: Vec<String>,
+    /// Exit status of the last pipeline, available as `$?`.
+    pub last_status: i32,
+    pub exit_requested: bool,
+    pub flow: Flow,
+    /// Number of loops currently running, which bounds `break` and `continue`.
+    pub loop_depth: usize,
+    /// Number of functions and sourced scripts running, where `return` works.
+    pub call_depth: usize,
+    pub held_fds: HeldFds,
+    pub jobs: Jobs,
+    /// Process id of the last background command, available as `$!`.
+    pub last_background: Option<i32>,
+    /// Working directory of a copy of the shell, such as a pipeline stage or
+    /// a background job, which must leave the process's own alone. `None`
+    /// for the shell itself.
+    cwd: Option<PathBuf>,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| (name, Variable { value, exported: true }))
+            .collect();
+
+        Shell {
+            vars,
+            history: History::default(),
+            aliases: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            scopes: Vec::new(),
+            abbreviations: BTreeMap::new(),
+            arg0: "heimdal".to_string(),
+            args: Vec::new(),
+            last_status: 0,
+            exit_requested: false,
+            flow: Flow::Normal,
+            loop_depth: 0,
+            call_depth: 0,
+            held_fds: HeldFds::default(),
+            jobs: Jobs::default(),
+            last_background: None,
+            cwd: None,
+        }
+    }
+
+    /// A copy of the shell to run commands in apart from it. A `cd` in the
+    /// copy only changes the copy's working directory.
+    pub fn subshell(&self) -> Shell {
+        let mut shell = self.clone();
+        if shell.cwd.is_none() {
+            // Empty when the directory is gone, which leaves relative paths
+            // to the process as before.
+            shell.cwd = Some(env::current_dir().unwrap_or_default());
+        }
+        shell
+    }
+
+    /// Whether this is a copy of the shell rather than the shell itself.
+    pub fn is_subshell(&self) -> bool {
+        self.cwd.is_some()
+    }
+
+    /// The directory relative paths are resolved against. It is empty for
+    /// the shell itself, so they stay relative to the process's directory.
+    pub fn base_dir(&self) -> &Path {
+        self.cwd.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// The working directory of a copy of the shell, which the commands it
+    /// starts are given.
+    pub fn own_dir(&self) -> Option<&Path> {
+        self.cwd.as_deref().filter(|dir| !dir.as_os_str().is_empty())
+    }
+
+    /// `path` relative to the shell's working directory.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.base_dir().join(path)
+    }
+
+    pub fn current_dir(&self) -> io::Result<PathBuf> {
+        match self.own_dir() {
+            Some(cwd) => Ok(cwd.to_path_buf()),
+            None => env::current_dir(),
+        }
+    }
+
+    /// Changes the working directory, which for a copy of the shell is only
+    /// its own.
+    pub fn set_current_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let Some(cwd) = &self.cwd else {
+            return env::set_current_dir(dir);
+        };
+        let dir = cwd.join(dir).canonicalize()?;
+        if !dir.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotADirectory, "Not a directory"));
+        }
+        self.cwd = Some(dir);
+        Ok(())
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Positional parameter `index`, where 0 is the script name.
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        match index {
+            0 => Some(&self.arg0),
+            _ => self.args.get(index - 1).map(String::as_str),
+        }
+    }
+
+    /// Sets a variable, keeping it exported if it already was.
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                self.vars.insert(name.to_string(), Variable { value, exported: false });
+            }
+        }
+    }
+
+    pub fn export_var(&mut self, name: &str, value: Option<String>) {
+        let var = self.vars.entry(name.to_string()).or_insert(Variable {
+            value: String::new(),
+            exported: true,
+        });
+        var.exported = true;
+        if let Some(value) = value {
+            var.value = value;
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Ends the innermost function scope, restoring the variables its
+    /// `local` declarations hid.
+    pub fn pop_scope(&mut self) {
+        for (name, previous) in self.scopes.pop().unwrap_or_default() {
+            match previous {
+                Some(var) => {
+                    self.vars.insert(name, var);
+                }
+                None => {
+                    self.vars.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Makes `name` local to the running function, unset unless a value is
+    /// given. Returns false outside a function.
+    pub fn declare_local(&mut self, name: &str, value: Option<String>) -> bool {
+        let Some(scope) = self.scopes.last_mut() else {
+            return false;
+        };
+        let previous = self.vars.remove(name);
+        scope.entry(name.to_string()).or_insert(previous);
+        if let Some(value) = value {
+            self.set_var(name, value);
+        }
+        true
+    }
+
+    /// The environment handed to external commands.
+    pub fn exported_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+    }
+
+    /// Whether the current list should stop, because of `exit`, `break`,
+    /// `continue` or Ctrl-C.
+    pub fn interrupted(&self) -> bool {
+        self.exit_requested || self.flow != Flow::Normal || signals::interrupted()
+    }
+
+    pub fn user(&self) -> &str {
+        let name = if cfg!(windows) { "USERNAME" } else { "USER" };
+        self.var(name).unwrap_or("unknown")
+    }
+}
+
+pub fn parse_command(words: Vec<String>) -> ShellCommand {
+    let mut parts = words.into_iter();
+    let command = parts.next().unwrap_or_default();
+    let args: Vec<String> = parts.collect();
+
+    match command.as_str() {
+        "exit" => ShellCommand::Exit(args.first().cloned()),
         "clear" => ShellCommand::Clear,
         "os" => ShellCommand::Os,
-        "tree" => ShellCommand::Tree,
+        "tree" => ShellCommand::Tree(args),
         "tip" | "fortune" => ShellCommand::Tip,
-        "cd" => args.get(0)
-            .map(|dir| ShellCommand::Cd(dir.clone()))
-            .unwrap_or(ShellCommand::Cd(String::new())),
-        "search" => args.get(0)
-            .map(|word| ShellCommand::Search(word.clone()))
-            .unwrap_or(ShellCommand::Search(String::new())),
+        "cd" => ShellCommand::Cd(args.first().cloned()),
+        "search" => ShellCommand::Search(args),
         "whoami" => ShellCommand::Whoami,
-        "heimdal" if args.get(0) == Some(&"--help".to_string()) => ShellCommand::HeimdalHelp,
+        "export" => ShellCommand::Export(args),
+        "unset" => ShellCommand::Unset(args),
+        "env" if args.is_empty() => ShellCommand::Env,
+        "history" => ShellCommand::History(args),
+        "source" | "." => ShellCommand::Source(args),
+        "alias" => ShellCommand::Alias(args),
+        "unalias" => ShellCommand::Unalias(args),
+        "abbr" => ShellCommand::Abbr(args),
+        "break" => ShellCommand::Break(args.first().cloned()),
+        "continue" => ShellCommand::Continue(args.first().cloned()),
+        "local" => ShellCommand::Local(args),
+        "return" => ShellCommand::Return(args.first().cloned()),
+        "jobs" => ShellCommand::Jobs(args),
+        "fg" => ShellCommand::Fg(args.first().cloned()),
+        "bg" => ShellCommand::Bg(args),
+        "wait" => ShellCommand::Wait(args),
+        "disown" => ShellCommand::Disown(args),
+        "heimdal" if args.first().is_some_and(|arg| arg == "--help") => ShellCommand::HeimdalHelp,
         "chat" => {
             if args.len() >= 4 && args[0] == "host" {
                 ShellCommand::Chat(ChatConfig {
@@ -49,105 +300,217 @@ fn parse_command(input: &str) -> ShellCommand {
             } else {
                 eprintln!("Usage: chat host <name> <port> <password>");
                 eprintln!("Usage: chat guest <name> <ip> <port> <password>");
-                ShellCommand::Unknown(command.to_string(), args)
+                ShellCommand::Unknown(command, args)
             }
         }
-        _ => ShellCommand::Unknown(command.to_string(), args),
+        _ => ShellCommand::Unknown(command, args),
     }
 }
 
+/// Entry point for the command line: `heimdal script [args]` runs a script,
+/// `heimdal -c command [name args]` runs a single command string and a
+/// non-terminal stdin is read as a script. Otherwise the shell is interactive.
+/// Returns the status the process should exit with.
+pub async fn start(args: Vec<String>) -> i32 {
+    signals::forward_termination();
+    let mut shell = Shell::new();
+    let mut args = args.into_iter();
+
+    let (name, source) = match args.next() {
+        Some(flag) if flag == "-c" => {
+            let Some(command) = args.next() else {
+                eprintln!("heimdal: -c: option requires an argument");
+                return 2;
+            };
+            if let Some(name) = args.next() {
+                shell.arg0 = name;
+            }
+            ("-c".to_string(), command)
+        }
+        Some(flag) if flag == "--help" || flag == "-h" => {
+            return match print_help(&mut io::stdout()) {
+                Ok(()) => 0,
+                Err(_) => 1,
+            };
+        }
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(script) => {
+                shell.arg0 = path.clone();
+                (path, script)
+            }
+            Err(e) => {
+                eprintln!("heimdal: {path}: {e}");
+                return 127;
+            }
+        },
+        None if !io::stdin().is_terminal() => {
+            let mut script = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut script) {
+                eprintln!("heimdal: failed to read stdin: {e}");
+                return 1;
+            }
+            ("stdin".to_string(), script)
+        }
+        None => return run(shell).await,
+    };
+
+    shell.args = args.collect();
+    run_script(&source, &name, &mut shell, &Io::inherit()).await;
+    shell.last_status
+}
+
+/// Runs a whole script in `shell` with the given streams. A syntax error
+/// anywhere in the script is reported with its name and nothing runs.
+pub async fn run_script(source: &str, name: &str, shell: &mut Shell, io: &Io) {
+    match parse(source, &shell.aliases) {
+        // Boxed because sourcing a script recurses back into `run_list`.
+        Ok(Some(list)) => Box::pin(run_list(&list, shell, io)).await,
+        Ok(None) => shell.last_status = 0,
+        Err(e) => {
+            eprintln!("heimdal: {name}: syntax error: {e}");
+            shell.last_status = 2;
+        }
+    }
+}
+
+/// Runs the system-wide and then the user's startup file, so settings such as
+/// `HISTFILE` are in place before the session starts. Missing files are fine.
+async fn run_rc_files(shell: &mut Shell) {
+    let user_rc = shell
+        .var("HOME")
+        .map(|home| Path::new(home).join(".heimdalrc"));
+
+    for path in [Some(PathBuf::from(SYSTEM_RC)), user_rc].into_iter().flatten() {
+        match fs::read_to_string(&path) {
+            Ok(script) => {
+                run_script(&script, &path.display().to_string(), shell, &Io::inherit()).await
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("heimdal: {}: {e}", path.display()),
+        }
+        if shell.exit_requested {
+            return;
+        }
+    }
+}
 
-pub async fn run() {
-    let mut rl = DefaultEditor::new().expect("Failed to create readline editor");
+async fn run(mut shell: Shell) -> i32 {
+    if let Err(e) = shell.jobs.enable_job_control() {
+        eprintln!("heimdal: no job control: {e}");
+    }
+    run_rc_files(&mut shell).await;
+    if shell.exit_requested {
+        return shell.last_status;
+    }
+    shell.history = History::load(|name| shell.var(name).map(str::to_string));
 
-    // if rl.load_history("history.txt").is_err() {
-    //     println!("No previous history.");
-    // }
+    let config = Config::builder()
+        .max_history_size(shell.history.max_size())
+        .expect("Invalid history size")
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = Editor::with_config(config).expect("Failed to create readline editor");
+    // After the editor, which installs a SIGINT handler of its own. At the
+    // prompt Ctrl-C arrives as a key anyway.
+    signals::catch_interrupts();
+    let helper = EditorHelper::default();
+    rl.bind_sequence(
+        KeyEvent(KeyCode::Char(' '), Modifiers::NONE),
+        EventHandler::Conditional(Box::new(AbbreviationHandler::new(
+            helper.abbreviations.clone(),
+        ))),
+    );
+    rl.set_helper(Some(helper));
+    sync_history(&mut rl, &shell.history);
 
+    // Leaving with stopped jobs takes a second `exit` in a row.
+    let mut warned_stopped = false;
+    let mut context = PromptContext { duration: None };
     loop {
-        let path = current_dir().unwrap();
-        let user = if cfg!(windows) {
-            var("USERNAME")
-        } else {
-            var("USER")
+        if let Err(e) = shell.jobs.notify(&mut io::stderr()).await {
+            eprintln!("heimdal: {e}");
         }
-            .unwrap_or_else(|_| "unknown".to_string());
+        let template = shell.var("PS1").unwrap_or(DEFAULT_PS1);
+        let prompt = prompt::render(template, &shell, &context);
 
-        let prompt = format!("\x1b[1;32m{user}@heimdal\x1b[0m:\x1b[1;34m{}\x1b[0m$ ", path.display());
+        if let Some(helper) = rl.helper_mut() {
+            helper.path = shell.var("PATH").unwrap_or_default().to_string();
+            helper.home = shell.var("HOME").map(str::to_string);
+            helper.aliases.clone_from(&shell.aliases);
+            helper.functions = shell.functions.keys().cloned().collect();
+            if let Ok(mut abbreviations) = helper.abbreviations.lock() {
+                abbreviations.clone_from(&shell.abbreviations);
+            }
+        }
 
         let readline = rl.readline(&prompt);
 
         match readline {
             Ok(line) => {
+                let line = match shell.history.expand(&line) {
+                    Ok(Some(expanded)) => {
+                        println!("{expanded}");
+                        expanded
+                    }
+                    Ok(None) => line,
+                    Err(e) => {
+                        eprintln!("heimdal: {e}");
+                        shell.last_status = 1;
+                        continue;
+                    }
+                };
                 let input = line.trim();
                 if input.is_empty() {
                     continue;
                 }
 
-                rl.add_history_entry(input).expect("Failed to add history entry");
-
-                let parsed_command = parse_command(&input);
-
-                match parsed_command {
-                    ShellCommand::Exit => break,
-                    ShellCommand::Clear => {
-                        print!("\x1B[2J\x1B[1;1H");
-                    }
-                    ShellCommand::Os => {
-                        let os = get_os();
-                        if os == "windows" {
-                            println!("You're running on Windows.");
-                        } else {
-                            println!("You're running on Linux.");
-                        }
-                    }
-                    ShellCommand::Tree => print_tree(Path::new("."), 0),
-                    ShellCommand::Tip => print_fortune(),
-                    ShellCommand::Search(word) => {
-                        if word.is_empty() {
-                            eprintln!("search: missing keyword");
-                        } else {
-                            search_files(&word).await;
-                        }
-                    }
-                    ShellCommand::Cd(dir) => {
-                        if dir.is_empty() {
-                            eprintln!("cd: missing operand");
-                        } else if let Err(e) = set_current_dir(Path::new(&dir)) {
-                            eprintln!("cd: {e}");
-                        }
+                let parsed = parse(input, &shell.aliases);
+                let commands = match &parsed {
+                    Ok(Some(list)) => list.simple_commands(),
+                    _ => Vec::new(),
+                };
+                match shell.history.add(line.trim_end(), &commands) {
+                    Ok(true) => {
+                        let _ = rl.add_history_entry(input);
                     }
-                    ShellCommand::Whoami => {
-                        let user = if cfg!(windows) {
-                            var("USERNAME")
-                        } else {
-                            var("USER")
-                        }
-                            .unwrap_or_else(|_| "unknown".to_string());
-
-                        println!("{user}");
-                    }
-                    ShellCommand::HeimdalHelp => print_help(),
-                    ShellCommand::Chat(config) => {
-                        if let Err(e) = chat::start_chat(config) {
-                            eprintln!("Chat error: {}", e);
-                        }
+                    Ok(false) => {}
+                    Err(e) => eprintln!("heimdal: failed to save history: {e}"),
+                }
+
+                let list = match parsed {
+                    Ok(Some(list)) => list,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        eprintln!("heimdal: syntax error: {e}");
+                        shell.last_status = 2;
+                        continue;
                     }
-                    ShellCommand::Unknown(cmd, args) => {
-                        match Command::new(&cmd).args(&args).spawn() {
-                            Ok(mut child) => {
-                                if let Err(e) = child.wait().await {
-                                    eprintln!("heimdal: command failed: {e}");
-                                }
-                            }
-                            Err(_) => eprintln!("heimdal: command not found: {cmd}"),
-                        }
+                };
+
+                signals::clear_interrupt();
+                let started = Instant::now();
+                run_list(&list, &mut shell, &Io::inherit()).await;
+                context.duration = Some(started.elapsed());
+                if signals::interrupted() {
+                    println!();
+                }
+                if shell.history.take_changed() {
+                    sync_history(&mut rl, &shell.history);
+                }
+                if shell.exit_requested {
+                    if warned_stopped || !shell.jobs.has_stopped() {
+                        break;
                     }
+                    eprintln!("There are stopped jobs.");
+                    shell.exit_requested = false;
+                    warned_stopped = true;
+                } else {
+                    warned_stopped = false;
                 }
             }
+            // Ctrl-C drops the line being edited, as in other shells.
             Err(ReadlineError::Interrupted) => {
-                println!("Ctrl-C");
-                break;
+                shell.last_status = 130;
             }
             Err(ReadlineError::Eof) => {
                 println!("Ctrl-D");
@@ -160,7 +523,35 @@ pub async fn run() {
         }
     }
 
-    // if rl.save_history("history.txt").is_err() {
-    //     eprintln!("Failed to save history.");
-    // }
-}
\ No newline at end of file
+    shell.last_status
+}
+
+/// Replaces the line editor's history with the shell's, used at startup and
+/// whenever entries were dropped.
+fn sync_history(rl: &mut Editor<EditorHelper, DefaultHistory>, history: &History) {
+    let _ = rl.clear_history();
+    for entry in history.entries() {
+        let _ = rl.add_history_entry(entry.as_str());
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cd_in_a_subshell_leaves_the_process_directory_alone() {
+        let before = env::current_dir().unwrap();
+        let shell = Shell::new();
+        let mut subshell = shell.subshell();
+
+        subshell.set_current_dir(Path::new("/")).unwrap();
+        subshell.set_current_dir(Path::new("tmp")).unwrap();
+        assert_eq!(subshell.current_dir().unwrap(), Path::new("/tmp").canonicalize().unwrap());
+        assert_eq!(subshell.resolve("x"), subshell.current_dir().unwrap().join("x"));
+        assert!(subshell.set_current_dir(Path::new("no-such-directory")).is_err());
+
+        assert_eq!(env::current_dir().unwrap(), before);
+        assert_eq!(shell.current_dir().unwrap(), before);
+        assert_eq!(shell.resolve("x"), Path::new("x"));
+    }
+}
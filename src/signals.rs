@@ -0,0 +1,59 @@
+use crate::jobs;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Set by Ctrl-C while the shell itself is running a command line, or when a
+/// foreground job dies of SIGINT, so the rest of the line is skipped.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGINT stop the running command line rather than the interactive
+/// shell, and ignores SIGQUIT like other shells. Commands get the default
+/// handling back when they start.
+pub fn catch_interrupts() {
+    // SAFETY: the handler only stores to an atomic, and `action` is fully
+    // initialised before `sigaction` reads it.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Called before each command line.
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// On SIGTERM or SIGHUP, passes the signal on to every job and exits, so
+/// killing the shell or closing its terminal does not leave commands behind.
+pub fn forward_termination() {
+    let (Ok(mut terminate), Ok(mut hangup)) =
+        (signal(SignalKind::terminate()), signal(SignalKind::hangup()))
+    else {
+        return;
+    };
+    tokio::spawn(async move {
+        let signal = tokio::select! {
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+        };
+        jobs::forward_on_exit(signal);
+        process::exit(128 + signal);
+    });
+}
@@ -0,0 +1,226 @@
+use crate::lexer::RedirectOp;
+use crate::parser::Redirect;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, PipeReader, PipeWriter, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::process::Stdio;
+use std::sync::Mutex;
+
+pub enum Input {
+    Stdin,
+    File(File),
+    Pipe(PipeReader),
+}
+
+pub enum Output {
+    Stdout,
+    Stderr,
+    File(File),
+    Pipe(PipeWriter),
+}
+
+/// The standard streams a command runs with.
+pub struct Io {
+    pub stdin: Input,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl Io {
+    /// The shell's own standard streams.
+    pub fn inherit() -> Io {
+        Io {
+            stdin: Input::Stdin,
+            stdout: Output::Stdout,
+            stderr: Output::Stderr,
+        }
+    }
+
+    /// Applies redirections left to right, so `> out.log 2>&1` sends both
+    /// streams to the file while `2>&1 > out.log` only moves stdout.
+    pub fn apply_redirects(&mut self, redirects: &[Redirect]) -> io::Result<()> {
+        for redirect in redirects {
+            match (redirect.op, redirect.fd) {
+                (RedirectOp::Read, 0) => {
+                    let file = open(&redirect.target, OpenOptions::new().read(true))?;
+                    self.stdin = Input::File(file);
+                }
+                (RedirectOp::Write, fd) => {
+                    let file = open(
+                        &redirect.target,
+                        OpenOptions::new().write(true).create(true).truncate(true),
+                    )?;
+                    *self.output_mut(fd)? = Output::File(file);
+                }
+                (RedirectOp::Append, fd) => {
+                    let file = open(
+                        &redirect.target,
+                        OpenOptions::new().append(true).create(true),
+                    )?;
+                    *self.output_mut(fd)? = Output::File(file);
+                }
+                (RedirectOp::Duplicate, fd) => {
+                    let source: u32 = redirect.target.parse().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{}: ambiguous redirect", redirect.target),
+                        )
+                    })?;
+                    let duplicate = self.output_mut(source)?.try_clone()?;
+                    *self.output_mut(fd)? = duplicate;
+                }
+                (RedirectOp::Read, fd) => return Err(bad_descriptor(fd)),
+            }
+        }
+        Ok(())
+    }
+
+    fn output_mut(&mut self, fd: u32) -> io::Result<&mut Output> {
+        match fd {
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(bad_descriptor(fd)),
+        }
+    }
+}
+
+fn open(path: &str, options: &OpenOptions) -> io::Result<File> {
+    options
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))
+}
+
+fn bad_descriptor(fd: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{fd}: bad file descriptor"),
+    )
+}
+
+impl Input {
+    pub fn try_clone(&self) -> io::Result<Input> {
+        Ok(match self {
+            Input::Stdin => Input::Stdin,
+            Input::File(file) => Input::File(file.try_clone()?),
+            Input::Pipe(reader) => Input::Pipe(reader.try_clone()?),
+        })
+    }
+
+    pub fn into_stdio(self) -> Stdio {
+        match self {
+            Input::Stdin => Stdio::inherit(),
+            Input::File(file) => file.into(),
+            Input::Pipe(reader) => reader.into(),
+        }
+    }
+}
+
+impl Output {
+    pub fn try_clone(&self) -> io::Result<Output> {
+        Ok(match self {
+            Output::Stdout => Output::Stdout,
+            Output::Stderr => Output::Stderr,
+            Output::File(file) => Output::File(file.try_clone()?),
+            Output::Pipe(writer) => Output::Pipe(writer.try_clone()?),
+        })
+    }
+
+    /// Whether the output goes to a terminal, where colour makes sense.
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            Output::Stdout => io::stdout().is_terminal(),
+            Output::Stderr => io::stderr().is_terminal(),
+            Output::File(file) => file.is_terminal(),
+            Output::Pipe(_) => false,
+        }
+    }
+
+    pub fn into_stdio(self) -> Stdio {
+        match self {
+            Output::Stdout => io::stdout().into(),
+            Output::Stderr => io::stderr().into(),
+            Output::File(file) => file.into(),
+            Output::Pipe(writer) => writer.into(),
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Stdin => io::stdin().read(buf),
+            Input::File(file) => file.read(buf),
+            Input::Pipe(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl AsRawFd for Input {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Input::Stdin => libc::STDIN_FILENO,
+            Input::File(file) => file.as_raw_fd(),
+            Input::Pipe(reader) => reader.as_raw_fd(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Stderr => io::stderr().write(buf),
+            Output::File(file) => file.write(buf),
+            Output::Pipe(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => file.flush(),
+            Output::Pipe(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Pipe ends behind the `/dev/fd` paths of `<(...)` and `>(...)`, which stay
+/// open until the pipeline using them has finished and are passed on to the
+/// external commands it starts. A copy of the shell gets its own duplicates.
+#[derive(Default)]
+pub struct HeldFds(Mutex<Vec<OwnedFd>>);
+
+impl Clone for HeldFds {
+    fn clone(&self) -> Self {
+        let fds = self.lock().iter().filter_map(|fd| fd.try_clone().ok()).collect();
+        HeldFds(Mutex::new(fds))
+    }
+}
+
+impl HeldFds {
+    /// Keeps `fd` open and returns its path.
+    pub fn hold(&self, fd: OwnedFd) -> String {
+        let path = format!("/dev/fd/{}", fd.as_raw_fd());
+        self.lock().push(fd);
+        path
+    }
+
+    pub fn raw_fds(&self) -> Vec<RawFd> {
+        self.lock().iter().map(AsRawFd::as_raw_fd).collect()
+    }
+
+    /// The number of descriptors held, to pass to `release` later.
+    pub fn mark(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Closes the descriptors held since `mark`.
+    pub fn release(&self, mark: usize) {
+        self.lock().truncate(mark);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<OwnedFd>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
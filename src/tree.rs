@@ -0,0 +1,521 @@
+use crate::commands::plural;
+use crate::ignore::Ignore;
+use crate::signals;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::SystemTime;
+
+const USAGE: &str = "usage: tree [-a] [-s] [-r] [-L DEPTH] [--sort name|size|mtime] [--no-ignore] [PATH]";
+
+/// Used when `LS_COLORS` is unset, with the colours `ls` has by default.
+const DEFAULT_LS_COLORS: &str = "di=01;34:ln=01;36:or=01;31:ex=01;32";
+
+const RESET: &str = "\x1b[0m";
+
+/// A parsed `tree` command line.
+struct Tree {
+    root: String,
+    max_depth: Option<usize>,
+    all: bool,
+    use_ignore: bool,
+    sizes: bool,
+    sort: Sort,
+    reverse: bool,
+    colours: Option<Colours>,
+}
+
+#[derive(PartialEq)]
+enum Sort {
+    Name,
+    /// Largest first.
+    Size,
+    /// Newest first.
+    Mtime,
+}
+
+/// An entry read from disk, with everything below it when it is a directory.
+struct Node {
+    name: String,
+    kind: Kind,
+    /// For directories, the total size of the files shown inside.
+    size: u64,
+    modified: SystemTime,
+    children: io::Result<Vec<Node>>,
+}
+
+enum Kind {
+    Dir,
+    File,
+    Executable,
+    Link { target: String, broken: bool },
+    Other,
+    /// An entry whose details could not be read, such as one removed while
+    /// the tree was being walked.
+    Unreadable(io::Error),
+}
+
+/// Colours from `LS_COLORS`, as `ls` uses them: `di`, `ln`, `or`, `ex` and
+/// `fi` for kinds of entry, and `*.rs` and the like for file names.
+struct Colours {
+    kinds: HashMap<String, String>,
+    suffixes: Vec<(String, String)>,
+}
+
+/// Prints the entries below `PATH`, or the current directory, with
+/// box-drawing lines joining each to its directory, followed by how many
+/// directories and files there were. `-L DEPTH` stops that many levels down.
+/// Hidden entries are left out unless `-a` is given, and so are `.git` and
+/// anything listed in `.gitignore` or `.ignore` files unless `--no-ignore`
+/// is. `-s` shows sizes, with the total of what is inside for directories.
+/// Entries are in name order, or largest or newest first with `--sort`, and
+/// `-r` reverses the order. Names are coloured by `LS_COLORS` on a terminal.
+/// A relative `PATH` is taken from `cwd`.
+pub fn tree(
+    args: &[String],
+    cwd: &Path,
+    colour: bool,
+    ls_colors: Option<&str>,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let colours = colour.then(|| Colours::parse(ls_colors.unwrap_or(DEFAULT_LS_COLORS)));
+    let tree = match Tree::parse(args, colours) {
+        Ok(tree) => tree,
+        Err(e) => {
+            writeln!(err, "tree: {e}")?;
+            writeln!(err, "{USAGE}")?;
+            return Ok(2);
+        }
+    };
+    let root = match cwd.join(&tree.root).canonicalize() {
+        Ok(root) => root,
+        Err(e) => {
+            writeln!(err, "tree: {}: {e}", tree.root)?;
+            return Ok(2);
+        }
+    };
+    let node = match tree.read(&root, tree.root.clone(), &Ignore::default(), 0) {
+        Ok(node) => node,
+        Err(e) => {
+            writeln!(err, "tree: {}: {e}", tree.root)?;
+            return Ok(2);
+        }
+    };
+    if signals::interrupted() {
+        return Ok(130);
+    }
+
+    let mut counts = (0, 0);
+    tree.print_entry(&node, out)?;
+    if let Ok(children) = &node.children {
+        tree.print(children, "", 1, &mut counts, out)?;
+    }
+    if !matches!(node.kind, Kind::Dir) {
+        counts.1 += 1;
+    }
+
+    let (dirs, files) = counts;
+    write!(
+        out,
+        "\n{}, {}",
+        plural(dirs, "directory", "directories"),
+        plural(files, "file", "files"),
+    )?;
+    if tree.sizes {
+        write!(out, ", {} in all", format_size(node.size))?;
+    }
+    writeln!(out)?;
+    Ok(if node.children.is_ok() { 0 } else { 1 })
+}
+
+impl Tree {
+    fn parse(args: &[String], colours: Option<Colours>) -> Result<Tree, String> {
+        let mut tree = Tree {
+            root: ".".to_string(),
+            max_depth: None,
+            all: false,
+            use_ignore: true,
+            sizes: false,
+            sort: Sort::Name,
+            reverse: false,
+            colours,
+        };
+        let mut root = None;
+        let mut args = args.iter();
+        let mut options_done = false;
+        while let Some(arg) = args.next() {
+            if options_done || !arg.starts_with('-') || arg == "-" {
+                if root.replace(arg.clone()).is_some() {
+                    return Err(format!("unexpected argument `{arg}`"));
+                }
+                continue;
+            }
+            match arg.as_str() {
+                "--" => options_done = true,
+                "--no-ignore" => tree.use_ignore = false,
+                "--sort" => tree.sort = parse_sort(args.next())?,
+                _ if arg.starts_with("--sort=") => {
+                    tree.sort = parse_sort(Some(&arg["--sort=".len()..].to_string()))?
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+                _ => {
+                    for (index, c) in arg.char_indices().skip(1) {
+                        match c {
+                            'a' => tree.all = true,
+                            's' => tree.sizes = true,
+                            'r' => tree.reverse = true,
+                            'L' => {
+                                // The depth may follow directly, as in `-L2`.
+                                let rest = &arg[index + 1..];
+                                let value = if rest.is_empty() {
+                                    args.next().cloned().ok_or("-L needs a depth")?
+                                } else {
+                                    rest.to_string()
+                                };
+                                match value.parse() {
+                                    Ok(depth) if depth > 0 => tree.max_depth = Some(depth),
+                                    _ => return Err(format!("-L: invalid depth `{value}`")),
+                                }
+                                break;
+                            }
+                            _ => return Err(format!("unknown option `-{c}`")),
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(root) = root {
+            tree.root = root;
+        }
+        Ok(tree)
+    }
+
+    /// Reads the entry at `path`, `depth` levels down, and for a directory
+    /// the entries shown inside it. Directories deeper than `-L` are only
+    /// read when their sizes are needed.
+    fn read(&self, path: &Path, name: String, parent: &Ignore, depth: usize) -> io::Result<Node> {
+        let meta = fs::symlink_metadata(path)?;
+        let file_type = meta.file_type();
+        let kind = if file_type.is_symlink() {
+            Kind::Link {
+                target: fs::read_link(path)?.display().to_string(),
+                broken: fs::metadata(path).is_err(),
+            }
+        } else if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_file() && meta.permissions().mode() & 0o111 != 0 {
+            Kind::Executable
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            Kind::Other
+        };
+        let mut node = Node {
+            name,
+            kind,
+            size: meta.len(),
+            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            children: Ok(Vec::new()),
+        };
+
+        let within = self.max_depth.is_none_or(|max| depth < max);
+        if let Kind::Dir = node.kind {
+            node.size = 0;
+            if within || self.sizes || self.sort == Sort::Size {
+                let ignore = if !self.use_ignore {
+                    Ignore::default()
+                } else if depth == 0 {
+                    Ignore::for_root(path)
+                } else {
+                    parent.child(path)
+                };
+                node.children = self.read_children(path, &ignore, depth + 1);
+                if let Ok(children) = &node.children {
+                    node.size = children.iter().map(|child| child.size).sum();
+                }
+            }
+        }
+        Ok(node)
+    }
+
+    fn read_children(&self, dir: &Path, ignore: &Ignore, depth: usize) -> io::Result<Vec<Node>> {
+        let mut children = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if signals::interrupted() {
+                break;
+            }
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !self.all && name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if self.use_ignore {
+                let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                if name == ".git" || ignore.is_ignored(&path, is_dir) {
+                    continue;
+                }
+            }
+            // One entry that cannot be read is shown with its error rather
+            // than losing the rest of the tree.
+            let node = match self.read(&path, name.clone(), ignore, depth) {
+                Ok(node) => node,
+                Err(e) => Node {
+                    name,
+                    kind: Kind::Unreadable(e),
+                    size: 0,
+                    modified: SystemTime::UNIX_EPOCH,
+                    children: Ok(Vec::new()),
+                },
+            };
+            children.push(node);
+        }
+        children.sort_by(|a, b| {
+            let order = match self.sort {
+                Sort::Name => Ordering::Equal,
+                Sort::Size => b.size.cmp(&a.size),
+                Sort::Mtime => b.modified.cmp(&a.modified),
+            };
+            order.then_with(|| a.name.cmp(&b.name))
+        });
+        if self.reverse {
+            children.reverse();
+        }
+        Ok(children)
+    }
+
+    /// Prints `nodes`, `depth` levels down, and what is inside them, counting
+    /// the directories and files.
+    fn print(
+        &self,
+        nodes: &[Node],
+        prefix: &str,
+        depth: usize,
+        counts: &mut (usize, usize),
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        for (index, node) in nodes.iter().enumerate() {
+            let last = index + 1 == nodes.len();
+            write!(out, "{prefix}{}", if last { "└── " } else { "├── " })?;
+            self.print_entry(node, out)?;
+            match node.kind {
+                Kind::Dir => {}
+                Kind::Unreadable(_) => continue,
+                _ => {
+                    counts.1 += 1;
+                    continue;
+                }
+            }
+            counts.0 += 1;
+            if self.max_depth.is_none_or(|max| depth < max) {
+                if let Ok(children) = &node.children {
+                    let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                    self.print(children, &prefix, depth + 1, counts, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints one entry's line after its connector.
+    fn print_entry(&self, node: &Node, out: &mut dyn Write) -> io::Result<()> {
+        if self.sizes {
+            write!(out, "[{:>5}]  ", format_size(node.size))?;
+        }
+        match self.colours.as_ref().and_then(|colours| colours.for_node(node)) {
+            Some(code) => write!(out, "\x1b[{code}m{}{RESET}", node.name)?,
+            None => write!(out, "{}", node.name)?,
+        }
+        match &node.kind {
+            Kind::Link { target, .. } => write!(out, " -> {target}")?,
+            Kind::Unreadable(e) => write!(out, "  [error: {e}]")?,
+            _ => {}
+        }
+        if let Err(e) = &node.children {
+            write!(out, "  [error opening dir: {e}]")?;
+        }
+        writeln!(out)
+    }
+}
+
+impl Colours {
+    fn parse(spec: &str) -> Colours {
+        let mut colours = Colours {
+            kinds: HashMap::new(),
+            suffixes: Vec::new(),
+        };
+        for entry in spec.split(':') {
+            let Some((key, code)) = entry.split_once('=') else {
+                continue;
+            };
+            match key.strip_prefix('*') {
+                Some(suffix) => colours.suffixes.push((suffix.to_string(), code.to_string())),
+                None => {
+                    colours.kinds.insert(key.to_string(), code.to_string());
+                }
+            }
+        }
+        colours
+    }
+
+    fn for_node(&self, node: &Node) -> Option<&str> {
+        let kind = |key: &str| self.kinds.get(key).map(String::as_str);
+        match &node.kind {
+            Kind::Dir => kind("di"),
+            Kind::Link { broken: true, .. } => kind("or").or_else(|| kind("ln")),
+            Kind::Link { .. } => kind("ln"),
+            Kind::Executable if self.kinds.contains_key("ex") => kind("ex"),
+            Kind::File | Kind::Executable => self
+                .suffixes
+                .iter()
+                .find(|(suffix, _)| node.name.ends_with(suffix.as_str()))
+                .map(|(_, code)| code.as_str())
+                .or_else(|| kind("fi")),
+            Kind::Other | Kind::Unreadable(_) => None,
+        }
+    }
+}
+
+fn parse_sort(value: Option<&String>) -> Result<Sort, String> {
+    match value.map(String::as_str) {
+        Some("name") => Ok(Sort::Name),
+        Some("size") => Ok(Sort::Size),
+        Some("mtime") => Ok(Sort::Mtime),
+        Some(other) => Err(format!("--sort: expected name, size or mtime, not `{other}`")),
+        None => Err("--sort needs name, size or mtime".to_string()),
+    }
+}
+
+/// `512`, `4.0K`, `12M`, as `ls -h` shows sizes.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{value:.1}{}", UNITS[unit])
+    } else {
+        format!("{value:.0}{}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// A small directory to draw, removed when dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let root = std::env::temp_dir().join(format!("heimdal-tree-{name}-{}", std::process::id()));
+            fs::create_dir_all(root.join("src/deep")).unwrap();
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+            fs::write(root.join("src/deep/a.txt"), "x").unwrap();
+            fs::write(root.join("docs/readme.md"), "0123456789").unwrap();
+            fs::write(root.join("out.log"), "log").unwrap();
+            fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+            fs::write(root.join("run.sh"), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+            symlink("src/main.rs", root.join("link")).unwrap();
+            Fixture(root)
+        }
+
+        fn draw(&self, args: &[&str], ls_colors: Option<&str>) -> String {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let mut out = Vec::new();
+            let status = tree(&args, &self.0, ls_colors.is_some(), ls_colors, &mut out, &mut Vec::new()).unwrap();
+            assert_eq!(status, 0);
+            String::from_utf8(out).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn draws_the_tree_without_hidden_or_ignored_entries() {
+        let fixture = Fixture::new("plain");
+        assert_eq!(
+            fixture.draw(&[], None),
+            "\
+.
+├── docs
+│   └── readme.md
+├── link -> src/main.rs
+├── run.sh
+└── src
+    ├── deep
+    │   └── a.txt
+    └── main.rs
+
+3 directories, 5 files
+"
+        );
+        let all = fixture.draw(&["-a", "--no-ignore", "-L", "1"], None);
+        assert!(all.contains("├── .gitignore\n") && all.contains("├── out.log\n"), "{all}");
+        assert!(all.ends_with("\n2 directories, 4 files\n"), "{all}");
+    }
+
+    #[test]
+    fn depth_sizes_and_order() {
+        let fixture = Fixture::new("options");
+        assert_eq!(
+            fixture.draw(&["-L", "1", "-r"], None),
+            "\
+.
+├── src
+├── run.sh
+├── link -> src/main.rs
+└── docs
+
+2 directories, 2 files
+"
+        );
+        assert_eq!(
+            fixture.draw(&["-s", "--sort", "size", "src"], None),
+            "\
+[   14]  src
+├── [   13]  main.rs
+└── [    1]  deep
+    └── [    1]  a.txt
+
+1 directory, 2 files, 14 in all
+"
+        );
+    }
+
+    #[test]
+    fn colours_follow_ls_colors() {
+        let fixture = Fixture::new("colours");
+        let drawn = fixture.draw(&["-L", "1"], Some("di=34:ex=32:ln=36:*.md=33"));
+        assert!(drawn.contains("── \x1b[34mdocs\x1b[0m\n"), "{drawn:?}");
+        assert!(drawn.contains("── \x1b[32mrun.sh\x1b[0m\n"), "{drawn:?}");
+        assert!(drawn.contains("── \x1b[36mlink\x1b[0m -> src/main.rs\n"), "{drawn:?}");
+        let docs = fixture.draw(&["docs"], Some("di=34:*.md=33"));
+        assert!(docs.contains("── \x1b[33mreadme.md\x1b[0m\n"), "{docs:?}");
+    }
+
+    #[test]
+    fn sizes_are_rounded_to_units() {
+        assert_eq!(format_size(1023), "1023");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(20 * 1024 * 1024), "20M");
+    }
+}